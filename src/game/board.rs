@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use color_eyre::Result;
 use color_eyre::eyre::{ContextCompat, eyre};
+use serde::{Deserialize, Serialize};
 use crate::game::tokens::token_deserializer::{TokenCategory, SlotPosition};
 use crate::game::game_communicator::GameCommunicator;
 use crate::game::id_types::PlayerId::{Player1, Player2};
@@ -11,7 +12,7 @@ use crate::game::game_service;
 use crate::game::id_types::{TokenInstanceId, location_ids, LocationId, PlayerId};
 use crate::game::locations::token_slot::TokenSlot;
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Board {
     pub side_1: BoardSide,
     pub side_2: BoardSide,
@@ -65,7 +66,7 @@ impl Board {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BoardSide {
     pub hero: LocationId,
     pub landscape: LocationId,
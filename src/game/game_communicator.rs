@@ -1,46 +1,255 @@
-use std::collections::{HashMap, vec_deque, VecDeque};
+use std::collections::{HashMap, HashSet, vec_deque, VecDeque};
 
+use color_eyre::eyre::{ContextCompat, eyre};
 use color_eyre::Result;
-use futures_util::{SinkExt, StreamExt};
+use futures_util::{FutureExt, StreamExt};
+use futures_util::stream::SplitStream;
 use owo_colors::{OwoColorize, Rgb, Style};
 use tokio::net::TcpStream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::WebSocketStream;
 
 use crate::game::tokens::token_deserializer::{TokenBehavior, TokenBehaviorTriggerWhenName};
-use crate::game::id_types::{TokenInstanceId, PlayerId, PromptInstanceId, ServerInstanceId};
+use crate::game::id_types::{TokenInstanceId, LocationId, PlayerId, PromptInstanceId, ServerInstanceId};
 use crate::game::instruction::InstructionToClient;
+use crate::game::output_sink::{MemoryOutput, OutgoingMessage, OutputSink, WebSocketOutput};
+use crate::game::protocol::WireFormat;
+use crate::game::replay::{ReplayEvent, ReplayRecorder};
+
+/// A client's websocket together with the wire format it asked for during the handshake.
+pub struct Connection {
+    pub websocket: WebSocketStream<TcpStream>,
+    pub format: WireFormat,
+    /// Messages read while the connection waited in the lobby, handed to the game first.
+    pub pending: Vec<Message>,
+}
+
+impl Connection {
+    pub fn new(websocket: WebSocketStream<TcpStream>, format: WireFormat) -> Self {
+        Self { websocket, format, pending: Vec::new() }
+    }
+
+    /// Checks a connection nobody reads from, e.g. while it waits for an opponent, without waiting on it.
+    pub fn is_closed(&mut self) -> bool {
+        loop {
+            match self.websocket.next().now_or_never() {
+                None => return false,
+                Some(Some(Ok(message))) if message.is_close() == false => self.pending.push(message),
+                Some(_) => return true,
+            }
+        }
+    }
+}
+
+pub enum ClientEvent {
+    Message(PlayerId, Message),
+    Disconnected(PlayerId),
+    /// A seat that dropped connected again through the lobby.
+    Rejoined(PlayerId, Connection),
+    /// A websocket closed. It only disconnects the seat if it is still the seat's latest connection,
+    /// `next_event` turns it into `Disconnected` or drops it and never returns it.
+    Closed(PlayerId, u64),
+}
 
 pub struct GameCommunicator {
-    websocket: WebSocketStream<TcpStream>,
-    server_style: Style,
+    outputs: HashMap<PlayerId, Box<dyn OutputSink>>,
+    formats: HashMap<PlayerId, WireFormat>,
+    incoming: UnboundedReceiver<ClientEvent>,
+    incoming_sender: UnboundedSender<ClientEvent>,
+    responding_to: Option<PlayerId>,
+    /// Seats that dropped and may still rejoin, nothing is sent to them in the meantime.
+    disconnected: HashSet<PlayerId>,
+    /// The latest connection of each seat, a socket replaced by a rejoin may still close later on.
+    connections: HashMap<PlayerId, u64>,
+    connections_made: u64,
+    recorder: Option<ReplayRecorder>,
+    /// The batch being sent, see `begin_batch`.
+    batch: Option<u64>,
+    batch_depth: u32,
+    batches_sent: u64,
+    /// Seats that already got the start of the current batch.
+    batch_recipients: HashSet<PlayerId>,
     client_style: Style,
 }
 
 impl GameCommunicator {
     pub fn new(websocket: WebSocketStream<TcpStream>) -> Self {
+        let mut communicator = Self::empty();
+        communicator.connect(PlayerId::Player1, Connection::new(websocket, WireFormat::Legacy));
+        communicator
+    }
+
+    pub fn new_match(player_1: Connection, player_2: Connection) -> Self {
+        let mut communicator = Self::empty();
+        communicator.connect(PlayerId::Player1, player_1);
+        communicator.connect(PlayerId::Player2, player_2);
+        communicator
+    }
+
+    /// A communicator without any transport. Everything sent to either seat is collected in the returned output.
+    pub fn headless() -> (Self, MemoryOutput) {
+        let output = MemoryOutput::new();
+        let mut communicator = Self::empty();
+        communicator.attach_output(PlayerId::Player1, Box::new(output.clone()));
+        communicator.attach_output(PlayerId::Player2, Box::new(output.clone()));
+        (communicator, output)
+    }
+
+    fn empty() -> Self {
+        let (incoming_sender, incoming) = unbounded_channel();
         Self {
-            websocket,
-            server_style: Style::new().color(Rgb(50, 150, 200)).bold(),
+            outputs: HashMap::new(),
+            formats: HashMap::new(),
+            incoming,
+            incoming_sender,
+            responding_to: None,
+            disconnected: HashSet::new(),
+            connections: HashMap::new(),
+            connections_made: 0,
+            recorder: None,
+            batch: None,
+            batch_depth: 0,
+            batches_sent: 0,
+            batch_recipients: HashSet::new(),
             client_style: Style::new().color(Rgb(50, 200, 150)).bold(),
         }
     }
 
-    pub async fn send_info(&mut self, info: &str) -> Result<()> {
-        println!("{} {}: {}", "(Server)".style(self.server_style), "Info".color(Rgb(150, 150, 150)), info);
-        self.websocket.send(Message::Text(format!("info|{}", info))).await?;
+    /// Binds a websocket to a seat. Messages read from it are tagged with that seat.
+    pub fn connect(&mut self, player_id: PlayerId, connection: Connection) {
+        self.disconnected.remove(&player_id);
+        self.formats.insert(player_id, connection.format);
+        let (sink, stream) = connection.websocket.split();
+        self.attach_output(player_id, Box::new(WebSocketOutput::new(sink, connection.format)));
+        self.connections_made += 1;
+        self.connections.insert(player_id, self.connections_made);
+        for message in connection.pending {
+            self.push_message(player_id, message);
+        }
+        tokio::spawn(forward_messages(player_id, self.connections_made, stream, self.incoming_sender.clone()));
+    }
+
+    /// Hands a message to the communicator as if a seat had sent it over its websocket.
+    pub fn push_message(&self, player_id: PlayerId, message: Message) {
+        self.push_event(ClientEvent::Message(player_id, message));
+    }
+
+    pub fn push_event(&self, event: ClientEvent) {
+        let _ = self.incoming_sender.send(event);
+    }
+
+    /// Lets the lobby hand a rejoining seat to the game.
+    pub fn event_sender(&self) -> UnboundedSender<ClientEvent> {
+        self.incoming_sender.clone()
+    }
+
+    /// Records every message read and sent from now on.
+    pub fn record_to(&mut self, recorder: ReplayRecorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Stops recording, e.g. while sending something that is not part of the game itself.
+    pub fn take_recorder(&mut self) -> Option<ReplayRecorder> {
+        self.recorder.take()
+    }
+
+    /// A replay that can't be written shouldn't end the game, recording just stops.
+    pub fn record(&mut self, event: ReplayEvent) {
+        let Some(recorder) = &mut self.recorder else { return };
+        if let Err(e) = recorder.record(&event) {
+            eprintln!("Stopped recording replay: {}", e);
+            self.recorder = None;
+        }
+    }
+
+    async fn record_sent(&mut self, player_id: PlayerId, message: &OutgoingMessage) -> Result<()> {
+        if self.recorder.is_some() {
+            let message = message.build(player_id).await?;
+            self.record(ReplayEvent::Sent { player_id, message });
+        }
         Ok(())
     }
 
-    pub async fn send_warning(&mut self, warning: &str) -> Result<()> {
-        println!("{} {}: {}", "(Server)".style(self.server_style), "Warning".color(Rgb(250, 200, 30)), warning);
-        self.websocket.send(Message::Text(format!("warn|{}", warning))).await?;
+    pub fn attach_output(&mut self, player_id: PlayerId, output: Box<dyn OutputSink>) {
+        self.disconnected.remove(&player_id);
+        self.outputs.insert(player_id, output);
+    }
+
+    pub fn set_format(&mut self, player_id: PlayerId, format: WireFormat) {
+        self.formats.insert(player_id, format);
+    }
+
+    /// Seats without a websocket, e.g. in a headless game, use the legacy format.
+    pub fn format_of(&self, player_id: PlayerId) -> WireFormat {
+        self.formats.get(&player_id).copied().unwrap_or_default()
+    }
+
+    pub fn is_connected(&self, player_id: PlayerId) -> bool {
+        self.outputs.contains_key(&player_id)
+    }
+
+    /// The seat whose message is currently being handled. Info, warnings and errors are only sent to this seat.
+    pub fn responding_to(&self) -> Option<PlayerId> {
+        self.responding_to
+    }
+
+    pub fn set_responding_to(&mut self, player_id: Option<PlayerId>) {
+        self.responding_to = player_id;
+    }
+
+    /// Groups everything sent until the matching `end_batch` into one numbered batch, so a client can apply it at once.
+    /// Each seat gets the start of the batch right before its first message in it, seats that get nothing see no batch at all.
+    /// Nested batches are folded into the outer one.
+    pub fn begin_batch(&mut self) {
+        self.batch_depth += 1;
+        if self.batch_depth == 1 {
+            self.batches_sent += 1;
+            self.batch = Some(self.batches_sent);
+        }
+    }
+
+    pub async fn end_batch(&mut self) -> Result<()> {
+        self.batch_depth = self.batch_depth.saturating_sub(1);
+        if self.batch_depth > 0 {
+            return Ok(());
+        }
+        let Some(batch) = self.batch.take() else { return Ok(()) };
+        for player_id in [PlayerId::Player1, PlayerId::Player2] {
+            if self.batch_recipients.remove(&player_id) {
+                self.deliver(player_id, OutgoingMessage::Instruction(InstructionToClient::EndBatch { batch })).await?;
+            }
+        }
         Ok(())
     }
 
+    /// How many batches were started so far, kept in snapshots so numbering carries on after a restore.
+    pub fn batches_sent(&self) -> u64 {
+        self.batches_sent
+    }
+
+    pub fn set_batches_sent(&mut self, batches_sent: u64) {
+        self.batches_sent = batches_sent;
+    }
+
+    pub async fn send_info(&mut self, info: &str) -> Result<()> {
+        self.send_to_responding(OutgoingMessage::Info(info.to_string())).await
+    }
+
+    pub async fn send_warning(&mut self, warning: &str) -> Result<()> {
+        self.send_to_responding(OutgoingMessage::Warning(warning.to_string())).await
+    }
+
     pub async fn send_error(&mut self, error: &str) -> Result<()> {
-        println!("{} {}: {}", "(Server)".style(self.server_style), "Error".color(Rgb(255, 50, 50)), error);
-        self.websocket.send(Message::Text(format!("error|{}", error))).await?;
+        self.send_to_responding(OutgoingMessage::Error(error.to_string())).await
+    }
+
+    /// Turns down an action of the seat being responded to. A token its client already moved is put back where it was.
+    pub async fn refuse(&mut self, reason: &str, rollback: Option<(TokenInstanceId, LocationId)>) -> Result<()> {
+        self.send_to_responding(OutgoingMessage::Instruction(InstructionToClient::Refused { reason: reason.to_string() })).await?;
+        if let Some((token, location)) = rollback {
+            self.send_to_responding(OutgoingMessage::Instruction(InstructionToClient::MoveToken { token, to: location })).await?;
+        }
         Ok(())
     }
 
@@ -48,21 +257,101 @@ impl GameCommunicator {
         &mut self,
         instruction: InstructionToClient,
     ) -> Result<()> {
-        let message = instruction.clone().build().await?;
-        println!("{} {}: {}", "(Server)".style(self.server_style), "Command".color(Rgb(80, 150, 120)), message.color(Rgb(120, 120, 120)));
-        self.websocket.send(Message::Text(message)).await?;
-        Ok(())
+        self.broadcast(OutgoingMessage::Instruction(instruction)).await
+    }
+
+    /// Sends an instruction built for the view of a single player.
+    pub async fn send_game_instruction_to(
+        &mut self,
+        player_id: PlayerId,
+        instruction: InstructionToClient,
+    ) -> Result<()> {
+        self.send_to(player_id, OutgoingMessage::Instruction(instruction)).await
     }
 
     pub async fn send_raw(&mut self, msg: &str) -> Result<()> {
-        println!("{} {}: {}", "(Server)".style(self.server_style), "Raw".color(Rgb(80, 150, 120)), msg.color(Rgb(120, 120, 120)));
-        self.websocket.send(Message::Text(msg.into())).await?;
+        self.broadcast(OutgoingMessage::Raw(msg.to_string())).await
+    }
+
+    /// Waits for the next message, treating a disconnect as the end of the conversation.
+    pub async fn read_message(&mut self) -> Result<(PlayerId, Message)> {
+        match self.next_event().await? {
+            ClientEvent::Message(player_id, msg) => Ok((player_id, msg)),
+            ClientEvent::Disconnected(player_id) => Err(eyre!("{} disconnected", player_id)),
+            ClientEvent::Rejoined(player_id, _) => Err(eyre!("{} rejoined a conversation that does not support rejoining", player_id)),
+            ClientEvent::Closed(..) => unreachable!("next_event never returns a closed connection"),
+        }
+    }
+
+    /// Waits for the next thing a client does. A seat that drops is kept open for it to rejoin,
+    /// only once both seats are gone there is nobody left to wait for.
+    pub async fn next_event(&mut self) -> Result<ClientEvent> {
+        let event = loop {
+            match self.incoming.recv().await.context("Failed to read message")? {
+                ClientEvent::Closed(player_id, connection) => {
+                    if self.connections.get(&player_id) == Some(&connection) {
+                        break ClientEvent::Disconnected(player_id);
+                    }
+                }
+                event => break event,
+            }
+        };
+        match &event {
+            ClientEvent::Message(player_id, msg) => {
+                println!("{} {} {}", "(Client)".style(self.client_style), format!("[{}]", player_id).color(Rgb(150, 150, 150)), msg);
+                self.responding_to = Some(*player_id);
+            }
+            ClientEvent::Disconnected(player_id) => {
+                self.outputs.remove(player_id);
+                self.disconnected.insert(*player_id);
+                if self.outputs.is_empty() {
+                    return Err(eyre!("Both players disconnected"));
+                }
+            }
+            ClientEvent::Rejoined(..) | ClientEvent::Closed(..) => {}
+        }
+        Ok(event)
+    }
+
+    async fn send_to(&mut self, player_id: PlayerId, message: OutgoingMessage) -> Result<()> {
+        if let Some(batch) = self.batch {
+            if self.batch_recipients.insert(player_id) {
+                self.deliver(player_id, OutgoingMessage::Instruction(InstructionToClient::BeginBatch { batch })).await?;
+            }
+        }
+        self.deliver(player_id, message).await
+    }
+
+    async fn deliver(&mut self, player_id: PlayerId, message: OutgoingMessage) -> Result<()> {
+        self.record_sent(player_id, &message).await?;
+        if self.disconnected.contains(&player_id) {
+            return Ok(());
+        }
+        let output = self.outputs.get_mut(&player_id).context(format!("{} is not connected", player_id))?;
+        output.send(player_id, message).await
+    }
+
+    async fn broadcast(&mut self, message: OutgoingMessage) -> Result<()> {
+        for player_id in [PlayerId::Player1, PlayerId::Player2] {
+            if self.outputs.contains_key(&player_id) || self.disconnected.contains(&player_id) {
+                self.send_to(player_id, message.clone()).await?;
+            }
+        }
         Ok(())
     }
 
-    pub async fn read_message(&mut self) -> Result<Message> {
-        let msg = self.websocket.next().await.expect("Failed to read message")?;
-        println!("{} {}", "(Client)".style(self.client_style), msg);
-        Ok(msg)
+    async fn send_to_responding(&mut self, message: OutgoingMessage) -> Result<()> {
+        match self.responding_to {
+            Some(player_id) => self.send_to(player_id, message).await,
+            None => self.broadcast(message).await,
+        }
     }
-}
\ No newline at end of file
+}
+
+async fn forward_messages(player_id: PlayerId, connection: u64, mut stream: SplitStream<WebSocketStream<TcpStream>>, incoming: UnboundedSender<ClientEvent>) {
+    while let Some(Ok(msg)) = stream.next().await {
+        if msg.is_close() { break }
+        if incoming.send(ClientEvent::Message(player_id, msg)).is_err() { return }
+    }
+    let _ = incoming.send(ClientEvent::Closed(player_id, connection));
+}
@@ -8,6 +8,7 @@ use crate::game::player::Player;
 use crate::game::state_resources::StateResources;
 
 use color_eyre::Result;
+use serde::{Deserialize, Serialize};
 
 pub mod context_keys {
     pub const OWNER: &str = "owner";
@@ -22,14 +23,19 @@ pub mod context_keys {
     pub const FROM_LOCATION: &str = "from_location";
     pub const TO_LOCATION: &str = "to_location";
     pub const CANCEL: &str = "cancel";
+    pub const CANCEL_REASON: &str = "cancel_reason";
     pub const SELECTED_TOKEN: &str = "selected_token";
     pub const IS_COUNTER_ATTACK: &str = "is_counter_attack";
+    pub const IS_EFFECT_SUMMON: &str = "is_effect_summon";
     pub const EFFECT_DAMAGE: &str = "effect_damage";
     pub const DRAWN_TOKEN: &str = "drawn_token";
     pub const CREATING_TOKEN: &str = "created_token";
+    pub const SELECTION_INTENTION: &str = "selection_intention";
+    pub const CAST_TOKEN: &str = "cast_token";
+    pub const CAST_TARGET: &str = "cast_target";
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct GameContext {
     pub values: HashMap<String, ContextValue>,
 }
@@ -64,7 +70,7 @@ impl GameContext {
     }
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum ContextValue {
     String(String),
     U64(u64),
@@ -0,0 +1,108 @@
+use std::collections::{HashMap, HashSet};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::game::game_communicator::{ClientEvent, Connection};
+use crate::game::id_types::PlayerId;
+
+pub enum LobbyJoin {
+    Waiting,
+    Matched {
+        player_1: Connection,
+        player_2: Connection,
+        /// What each connection presented, a saved game is only resumed for both of its seats.
+        rejoin_tokens: [Option<String>; 2],
+    },
+    /// The connection took its seat back in a running game.
+    Rejoined,
+    /// The session is still being played and the connection holds none of its seats.
+    Refused(Connection),
+}
+
+/// A game in progress that dropped connections can return to.
+pub struct RunningSession {
+    pub seats: HashMap<String, PlayerId>,
+    pub events: UnboundedSender<ClientEvent>,
+}
+
+pub struct GameLobby {
+    waiting: HashMap<String, (Connection, Option<String>)>,
+    running: HashMap<String, RunningSession>,
+    /// Sessions that were matched but whose game has not started yet.
+    starting: HashSet<String>,
+}
+
+impl GameLobby {
+    pub fn new() -> Self {
+        Self {
+            waiting: HashMap::new(),
+            running: HashMap::new(),
+            starting: HashSet::new(),
+        }
+    }
+
+    /// Seats a connection in the given session. The first connection waits as Player 1, the second one completes the match as Player 2.
+    /// A connection with the rejoin token of a running game goes straight back to its seat.
+    pub fn join(&mut self, session: &str, rejoin_token: Option<&str>, connection: Connection) -> LobbyJoin {
+        let seat = self.running.get(session).zip(rejoin_token).and_then(|(running, token)| running.seats.get(token).map(|seat| (running, *seat)));
+        let connection = match seat {
+            Some((running, seat)) => match running.events.send(ClientEvent::Rejoined(seat, connection)) {
+                Ok(()) => return LobbyJoin::Rejoined,
+                // The game ended before it got the connection back
+                Err(error) => {
+                    self.running.remove(session);
+                    let ClientEvent::Rejoined(_, connection) = error.0 else { unreachable!() };
+                    connection
+                }
+            },
+            None => connection,
+        };
+
+        // A second game under the same name would take the seats of the first one
+        if self.running.contains_key(session) || self.starting.contains(session) {
+            return LobbyJoin::Refused(connection);
+        }
+
+        let rejoin_token = rejoin_token.map(|token| token.to_string());
+        // A player that left while waiting would never get to see the game
+        let waiting = self.waiting.remove(session).and_then(|(mut player_1, first_token)| (player_1.is_closed() == false).then_some((player_1, first_token)));
+        match waiting {
+            Some((player_1, first_token)) => {
+                self.starting.insert(session.to_string());
+                LobbyJoin::Matched { player_1, player_2: connection, rejoin_tokens: [first_token, rejoin_token] }
+            }
+            None => {
+                self.waiting.insert(session.to_string(), (connection, rejoin_token));
+                LobbyJoin::Waiting
+            }
+        }
+    }
+
+    pub fn start_session(&mut self, session: &str, running: RunningSession) {
+        self.starting.remove(session);
+        self.running.insert(session.to_string(), running);
+    }
+
+    /// Frees the session name, whether its game ended or never got to start.
+    pub fn end_session(&mut self, session: &str) {
+        self.starting.remove(session);
+        self.running.remove(session);
+    }
+}
+
+/// The session to play in. Connections without one would be paired with strangers, so there is no default.
+pub fn get_session(query: Option<&str>) -> Option<String> {
+    get_query_value(query, "session").filter(|session| session.is_empty() == false)
+}
+
+pub fn get_rejoin_token(query: Option<&str>) -> Option<String> {
+    get_query_value(query, "rejoin")
+}
+
+fn get_query_value(query: Option<&str>, key: &str) -> Option<String> {
+    query.unwrap_or("")
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value.to_string())
+}
@@ -0,0 +1,71 @@
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::id_types::PlayerId;
+use crate::game::state_resources::StateResources;
+
+/// Why a game ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameOverReason {
+    HeroDefeated,
+    /// The loser had to draw from an empty set.
+    DeckOut,
+    Concede,
+}
+
+impl Display for GameOverReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameOverReason::HeroDefeated => write!(f, "hero_defeated"),
+            GameOverReason::DeckOut => write!(f, "deck_out"),
+            GameOverReason::Concede => write!(f, "concede"),
+        }
+    }
+}
+
+/// How the board of one player looked when the game ended.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerSummary {
+    pub player_id: PlayerId,
+    pub hero_health: i32,
+    pub tokens_in_set: usize,
+    pub tokens_in_hand: usize,
+    pub tokens_in_graveyard: usize,
+}
+
+/// Sent along with the end of the game, so clients can show a result screen.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GameSummary {
+    pub rounds: u32,
+    pub seed: u64,
+    pub players: Vec<PlayerSummary>,
+}
+
+impl GameSummary {
+    pub fn of(resources: &StateResources) -> Self {
+        let count = |location| resources.locations.get(&location).map_or(0, |location| location.get_tokens().len());
+        let players = [PlayerId::Player1, PlayerId::Player2].into_iter().map(|player_id| {
+            let player = resources.get_player(player_id);
+            PlayerSummary {
+                player_id,
+                hero_health: resources.token_instances.get(&player.hero).map_or(0, |hero| hero.current_stats.health),
+                tokens_in_set: count(player.set),
+                tokens_in_hand: count(player.hand),
+                tokens_in_graveyard: count(resources.board.get_side(player_id).graveyard),
+            }
+        }).collect();
+
+        Self { rounds: resources.round, seed: resources.rng.seed, players }
+    }
+}
+
+/// A decided game. It stays around until a new game starts, so late commands can be refused and a rematch agreed on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GameOver {
+    pub winner: PlayerId,
+    pub reason: GameOverReason,
+    pub summary: GameSummary,
+    pub rematch_requests: Vec<PlayerId>,
+}
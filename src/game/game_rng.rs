@@ -0,0 +1,60 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Ids and seeds the server hands out stay below 2^53, so JSON clients that read numbers as doubles get them back exactly.
+pub const JSON_SAFE_LIMIT: u64 = 1 << 53;
+
+/// The only source of randomness in a game. Given the same seed and the same inputs a match plays out exactly the same.
+/// The state is atomic so targets can be picked at random while the game state is only borrowed.
+#[derive(Debug)]
+pub struct GameRng {
+    pub seed: u64,
+    state: AtomicU64,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    /// A game nobody asked to reproduce still gets a seed, so it can be replayed later.
+    pub fn from_entropy() -> Self {
+        Self::new(fastrand::u64(..JSON_SAFE_LIMIT))
+    }
+
+    /// Picks up where another generator left off, see `state`.
+    pub fn restore(seed: u64, state: u64) -> Self {
+        Self {
+            seed,
+            state: AtomicU64::new(state),
+        }
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state.load(Ordering::Relaxed)
+    }
+
+    fn next<T>(&self, f: impl FnOnce(&fastrand::Rng) -> T) -> T {
+        let rng = fastrand::Rng::with_seed(self.state.load(Ordering::Relaxed));
+        let value = f(&rng);
+        self.state.store(rng.get_seed(), Ordering::Relaxed);
+        value
+    }
+
+    /// For anything that is sent to clients, e.g. instance ids.
+    pub fn json_safe_u64(&self) -> u64 {
+        self.next(|rng| rng.u64(..JSON_SAFE_LIMIT))
+    }
+
+    /// Panics on an empty range, check for empty collections first.
+    pub fn usize(&self, range: Range<usize>) -> usize {
+        self.next(|rng| rng.usize(range))
+    }
+
+    pub fn bool(&self) -> bool {
+        self.next(|rng| rng.bool())
+    }
+}
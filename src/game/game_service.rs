@@ -1,6 +1,7 @@
 use std::char::MAX;
 use std::collections::{HashMap, VecDeque};
 use std::fmt::{Display, Formatter};
+use std::fs;
 use std::num::ParseIntError;
 use std::str::FromStr;
 
@@ -15,13 +16,19 @@ use tokio_tungstenite::WebSocketStream;
 
 use crate::game::animation_presets::AnimationPreset;
 use crate::game::board::Board;
-use crate::game::game_communicator::GameCommunicator;
+use crate::game::game_communicator::{ClientEvent, Connection, GameCommunicator};
+use crate::game::game_lobby::RunningSession;
 use crate::game::game_context::{ContextValue, GameContext};
+use crate::game::game_over::GameOverReason;
 use crate::game::id_types::{location_ids, LocationId, PlayerId, PromptInstanceId, ServerInstanceId, TokenInstanceId};
 use crate::game::id_types::PlayerId::{Player1, Player2};
 use crate::game::instruction::InstructionToClient;
 use crate::game::new_state_machine::StateMachine;
 use crate::game::player::Player;
+use crate::game::protocol::InstructionFromClient;
+use crate::game::replay::{ReplayEvent, ReplayRecorder};
+use crate::game::snapshot;
+use crate::game::snapshot::GameSnapshot;
 use crate::game::prompts::{PromptCallback, PromptCallbackClosure, PromptCallbackResult, PromptInstance, PromptProfile, PromptType};
 use crate::game::state_resources::StateResources;
 use crate::game::tag::get_tag;
@@ -30,90 +37,253 @@ use crate::game::tokens::token_behaviors::TokenBehaviorResult;
 use crate::game::tokens::token_deserializer::{TokenBehaviorTriggerWhenName, TokenCategory};
 use crate::game::tokens::token_instance::TokenInstance;
 use crate::game::tokens::token_registry::TokenRegistry;
+use crate::GAME_LOBBY;
 
-pub async fn game_service(websocket: WebSocketStream<TcpStream>) -> Result<()> {
-    println!("Starting game service");
+pub async fn game_service(session: String, player_1: Connection, player_2: Connection, presented: [Option<String>; 2]) -> Result<()> {
+    println!("Starting game service for session \"{}\"", session);
 
-    let mut communicator = GameCommunicator::new(websocket);
-    let mut state = StateMachine::new();
-    let mut resources = StateResources::new();
-    let mut current_callback: Option<PromptCallback> = None;
-    let mut callback_context = GameContext::new();
+    let snapshot = GameSnapshot::find([presented[0].as_deref(), presented[1].as_deref()]);
+    let (game_id, rejoin_tokens, player_1, player_2) = match &snapshot {
+        Some(snapshot) => {
+            // The connections may come back in any order, each one takes the seat of its token
+            let (player_1, player_2) = match presented[0].as_deref().and_then(|token| snapshot.seat_of(token)) {
+                Some(PlayerId::Player2) => (player_2, player_1),
+                _ => (player_1, player_2),
+            };
+            let Some(game_id) = presented[0].as_deref().and_then(snapshot::game_id_of) else {
+                GAME_LOBBY.lock().await.end_session(&session);
+                return Err(eyre!("Resumed game has no id"));
+            };
+            let game_id = game_id.to_string();
+            (game_id, snapshot.seats.iter().cloned().collect::<HashMap<String, PlayerId>>(), player_1, player_2)
+        }
+        None => {
+            let game_id = format!("{:016x}", fastrand::u64(..));
+            let rejoin_tokens = HashMap::from([
+                (snapshot::new_rejoin_token(&game_id), PlayerId::Player1),
+                (snapshot::new_rejoin_token(&game_id), PlayerId::Player2),
+            ]);
+            (game_id, rejoin_tokens, player_1, player_2)
+        }
+    };
+
+    let communicator = GameCommunicator::new_match(player_1, player_2);
+    GAME_LOBBY.lock().await.start_session(&session, RunningSession { seats: rejoin_tokens.clone(), events: communicator.event_sender() });
+
+    let result = run_game(&session, &game_id, communicator, &rejoin_tokens, snapshot).await;
+    GAME_LOBBY.lock().await.end_session(&session);
+    result
+}
+
+/// Plays a matched game until nobody holds a seat anymore. Seats that drop can come back through the lobby.
+pub async fn run_game(session: &str, game_id: &str, communicator: GameCommunicator, rejoin_tokens: &HashMap<String, PlayerId>, snapshot: Option<GameSnapshot>) -> Result<()> {
+    let snapshot_path = GameSnapshot::path(game_id);
+    let restored = snapshot.is_some();
+    let mut game = match snapshot {
+        Some(snapshot) => GameSession::restore(snapshot, communicator).await?,
+        None => GameSession::new(communicator),
+    };
+
+    for player_id in [PlayerId::Player1, PlayerId::Player2] {
+        game.join_session(session, player_id, rejoin_tokens).await?;
+        if restored {
+            game.resend_state(player_id).await?;
+        }
+    }
+
+    // Joining is left out of the replay, the rejoin tokens are different every time
+    match ReplayRecorder::create(&ReplayRecorder::session_path(session)) {
+        Ok(recorder) => game.communicator.record_to(recorder),
+        Err(e) => eprintln!("Unable to record replay: {}", e),
+    }
 
     loop {
-        let msg = communicator.read_message().await?;
+        let event = match game.communicator.next_event().await {
+            Ok(event) => event,
+            Err(e) => {
+                // Nobody holds a seat anymore, the game is abandoned
+                let _ = fs::remove_file(&snapshot_path);
+                return Err(e);
+            }
+        };
+        let (player_id, msg) = match event {
+            ClientEvent::Message(player_id, msg) => (player_id, msg),
+            ClientEvent::Disconnected(player_id) => {
+                println!("{} disconnected from session \"{}\", waiting for them to rejoin", player_id, session);
+                continue;
+            }
+            ClientEvent::Rejoined(player_id, connection) => {
+                println!("{} rejoined session \"{}\"", player_id, session);
+                game.communicator.connect(player_id, connection);
+                // A resync only repeats what the replay already holds
+                let recorder = game.communicator.take_recorder();
+                game.join_session(session, player_id, rejoin_tokens).await?;
+                game.resend_state(player_id).await?;
+                if let Some(recorder) = recorder {
+                    game.communicator.record_to(recorder);
+                }
+                continue;
+            }
+            ClientEvent::Closed(..) => continue,
+        };
 
         let message = msg.into_text().unwrap();
 
-        let [instruction, data] = message.split('|').collect::<Vec<_>>()[..] else {
-            println!("Could not execute invalid instruction.");
-            continue;
+        if let Err(e) = game.handle_message(player_id, &message).await {
+            // Resuming would run into the same fault again
+            let _ = fs::remove_file(&snapshot_path);
+            return Err(e);
+        }
+        if game.resources.game_over.is_some() {
+            // A decided game has nothing left to resume, the players stay connected for a rematch
+            let _ = fs::remove_file(&snapshot_path);
+        } else {
+            let mut snapshot = game.snapshot();
+            snapshot.seats = rejoin_tokens.iter().map(|(token, seat)| (token.clone(), *seat)).collect();
+            snapshot.seats.sort_by_key(|(_, seat)| *seat as u32);
+            if let Err(e) = snapshot.save(&snapshot_path) {
+                eprintln!("Unable to save snapshot: {}", e);
+            }
+        }
+    }
+}
+
+/// Everything a single match needs. The websocket service feeds it messages as they arrive,
+/// but it can just as well be driven in memory with a headless communicator.
+pub struct GameSession {
+    pub communicator: GameCommunicator,
+    pub state: StateMachine,
+    pub resources: StateResources,
+    pub current_callback: Option<PromptCallback>,
+    pub callback_context: GameContext,
+}
+
+impl GameSession {
+    pub fn new(communicator: GameCommunicator) -> Self {
+        Self {
+            communicator,
+            state: StateMachine::new(),
+            resources: StateResources::new(),
+            current_callback: None,
+            callback_context: GameContext::new(),
+        }
+    }
+
+    /// Tells a seat which session it plays in and how to get back into it.
+    pub async fn join_session(&mut self, session: &str, player_id: PlayerId, rejoin_tokens: &HashMap<String, PlayerId>) -> Result<()> {
+        let rejoin_token = rejoin_tokens.iter().find(|(_, seat)| **seat == player_id).map(|(token, _)| token.clone()).context("Seat has no rejoin token")?;
+        self.communicator.send_game_instruction_to(player_id, InstructionToClient::JoinSession { session: session.to_string(), player_id, rejoin_token }).await
+    }
+
+    /// Reads a message in the wire format of the seat that sent it.
+    pub async fn handle_message(&mut self, player_id: PlayerId, message: &str) -> Result<()> {
+        let command = match InstructionFromClient::parse(self.communicator.format_of(player_id), message) {
+            Ok(command) => command,
+            Err(e) => {
+                // The game never sees the message, so neither does the replay
+                let recorder = self.communicator.take_recorder();
+                self.communicator.set_responding_to(Some(player_id));
+                self.communicator.send_error(&e.to_string()).await?;
+                if let Some(recorder) = recorder {
+                    self.communicator.record_to(recorder);
+                }
+                return Ok(());
+            }
         };
+        self.handle_command(player_id, command).await
+    }
+
+    /// Applies a command. Everything it causes, prompt updates included, reaches the clients as one batch.
+    pub async fn handle_command(&mut self, player_id: PlayerId, command: InstructionFromClient) -> Result<()> {
+        self.communicator.begin_batch();
+        let result = self.apply_command(player_id, command).await;
+        self.communicator.end_batch().await?;
+        result
+    }
+
+    async fn apply_command(&mut self, player_id: PlayerId, command: InstructionFromClient) -> Result<()> {
+        let Self { communicator, state, resources, current_callback, callback_context } = self;
+        communicator.set_responding_to(Some(player_id));
+        communicator.record(ReplayEvent::Received { player_id, command: command.clone() });
 
-        if let Some(callback) = &mut current_callback {
-            if instruction == "callback" {
-                match callback.execute(data.to_string(), &mut callback_context, &mut state, &mut resources, &mut communicator)? {
+        if let Err(e) = check_seat(player_id, &command, resources, current_callback) {
+            communicator.refuse(&e.to_string(), rollback_for(&command, resources)).await?;
+            return Ok(());
+        }
+
+        if let Some(callback) = current_callback {
+            if let InstructionFromClient::Callback { callback_id, value } = command {
+                let result = match callback.execute(player_id, callback_id, value, callback_context, state, resources, communicator) {
+                    Ok(result) => result,
+                    Err(e) => {
+                        communicator.refuse(&e.to_string(), None).await?;
+                        return Ok(());
+                    }
+                };
+                match result {
                     PromptCallbackResult::Keep => { },
                     PromptCallbackResult::End(new_callback) => {
-                        callback.cancel(&mut communicator).await?;
+                        callback.cancel(communicator).await?;
                         if let Some(callback) = &new_callback {
-                            callback.create_instructions(&mut communicator).await?;
+                            callback.create_instructions(communicator).await?;
                         }
-                        current_callback = new_callback;
+                        *current_callback = new_callback;
 
-                        if current_callback.is_some() { continue }
+                        if current_callback.is_some() { return Ok(()) }
                     }
                 }
+            } else if callback.cancelable || command == InstructionFromClient::Concede {
+                callback.cancel(communicator).await?;
             } else {
-                if callback.cancelable {
-                    callback.cancel(&mut communicator).await?;
-                } else {
-                    todo!() // How to properly refuse a player action?
-                }
+                // The prompt stays open until it is answered
+                communicator.refuse("Answer the open prompt first", rollback_for(&command, resources)).await?;
+                return Ok(());
             }
         }
 
-        let result = match instruction {
-            "start_game" => {
-                state = StateMachine::new();
-                state.start_game(data, &mut resources, &mut communicator).await
+        let result = match command {
+            InstructionFromClient::StartGame { set_1, set_2, seed } => {
+                *state = StateMachine::new();
+                state.start_game(&set_1, &set_2, seed, resources, communicator).await
             },
-            "move_token" => {
-                let token_instance_id = get_tag("token", data)?.parse::<TokenInstanceId>()?;
-                let target_location_id = get_tag("location", data)?.parse::<LocationId>()?;
-                match resources.token_instances.get(&token_instance_id).context("Token to move not found")?.token_data.token_category {
-                    TokenCategory::Unit {..} => {
-                        if resources.can_player_summon_unit(token_instance_id, target_location_id, &mut communicator).await? {
-                            state.summon_token(token_instance_id, target_location_id);
-                        }
-                    },
-                    TokenCategory::Item {..} => {
-                        if resources.can_player_equip_item(token_instance_id, target_location_id, &mut communicator).await? {
-                            let equipping_unit_id = resources.equipment_slot_owners.get(&target_location_id).context("This location is not an equipment slot")?;
-                            state.equip_item(*equipping_unit_id, token_instance_id);
-                        }
-                    },
-                    _ => {}
+            InstructionFromClient::MoveToken { token, location } => {
+                let rollback = rollback_for(&command, resources);
+                match play_token(token, location, state, resources, communicator).await {
+                    Ok(()) => Ok(()),
+                    // The client already shows the token where it was dropped
+                    Err(e) => communicator.refuse(&e.to_string(), rollback).await,
                 }
-                Ok(())
             }
-            "pass_turn" => {
+            InstructionFromClient::PassTurn => {
                 let mut cancel = false;
-                if let Some(callback) = &mut current_callback {
+                if let Some(callback) = current_callback {
                     if callback.cancelable {
-                        callback.cancel(&mut communicator).await?;
-                        current_callback = None;
+                        callback.cancel(communicator).await?;
+                        *current_callback = None;
                     } else {
                         cancel = true;
                     }
                 }
                 if cancel == false {
-                    resources.set_current_turn(resources.current_turn.opponent(), &mut state, &mut communicator).await?;
+                    state.pass_turn(resources.current_turn);
                 }
                 Ok(())
             },
-            "callback" => { Ok(()) }
-            _ => Err(eyre!("Unknown instruction: {}", instruction)),
+            InstructionFromClient::Callback { .. } => { Ok(()) }
+            InstructionFromClient::Concede => {
+                *current_callback = None;
+                resources.end_game(player_id.opponent(), GameOverReason::Concede, communicator).await
+            }
+            InstructionFromClient::Rematch => {
+                if resources.request_rematch(player_id, communicator).await? == false {
+                    return Ok(());
+                }
+                let (set_1, set_2) = resources.sets.clone();
+                // Drawn from the finished game, so replays of the session play the rematch the same way
+                let seed = resources.rng.json_safe_u64();
+                *state = StateMachine::new();
+                state.start_game(&set_1, &set_2, Some(seed), resources, communicator).await
+            }
         };
 
         match result {
@@ -125,13 +295,96 @@ pub async fn game_service(websocket: WebSocketStream<TcpStream>) -> Result<()> {
             }
         }
 
-        if let Some(callback) = state.process(&mut resources, &mut communicator).await? {
-            callback.create_instructions(&mut communicator).await?;
-            current_callback = Some(callback);
+        let callback = state.process(resources, communicator).await?;
+        if resources.game_over.is_some() {
+            // Nothing is left to answer, clients drop their prompts when the game ends
+            *current_callback = None;
+            return Ok(());
+        }
+
+        if let Some(callback) = callback {
+            // Prompts raised by a transition group answer with the context of that group
+            *callback_context = callback.context.clone();
+            callback.create_instructions(communicator).await?;
+            *current_callback = Some(callback);
         } else {
-            let callback = resources.show_selectable_tokens(&mut communicator).await?;
-            callback.create_instructions(&mut communicator).await?;
-            current_callback = Some(callback);
+            let callback = resources.show_selectable_tokens(communicator).await?;
+            callback.create_instructions(communicator).await?;
+            *current_callback = Some(callback);
+        }
+
+        Ok(())
+    }
+}
+
+async fn play_token(token_instance_id: TokenInstanceId, target_location_id: LocationId, state: &mut StateMachine, resources: &mut StateResources, communicator: &mut GameCommunicator) -> Result<()> {
+    match resources.token_instances.get(&token_instance_id).context("Token to move not found")?.token_data.token_category {
+        TokenCategory::Unit {..} => {
+            if resources.can_player_summon_unit(token_instance_id, target_location_id, communicator).await? {
+                state.summon_token(token_instance_id, target_location_id);
+            }
+        },
+        TokenCategory::Item {..} => {
+            if resources.can_player_equip_item(token_instance_id, target_location_id, communicator).await? {
+                let equipping_unit_id = resources.equipment_slot_owners.get(&target_location_id).context("This location is not an equipment slot")?;
+                state.equip_item(*equipping_unit_id, token_instance_id);
+            }
+        },
+        TokenCategory::Command {..} => {
+            if resources.can_player_cast_command(token_instance_id, target_location_id, communicator).await? {
+                state.cast_command(token_instance_id);
+            }
+        },
+        _ => return Err(eyre!("This token can't be played")),
+    }
+    Ok(())
+}
+
+/// Where a token the client moved on its own has to go back to when the move is refused.
+fn rollback_for(command: &InstructionFromClient, resources: &StateResources) -> Option<(TokenInstanceId, LocationId)> {
+    let InstructionFromClient::MoveToken { token, .. } = command else { return None };
+    resources.token_instances.get(token).map(|instance| (*token, instance.location))
+}
+
+/// Rejects messages sent from the wrong seat before they can touch the game state.
+fn check_seat(player_id: PlayerId, command: &InstructionFromClient, resources: &StateResources, current_callback: &Option<PromptCallback>) -> Result<()> {
+    if resources.game_over.is_some() && matches!(command, InstructionFromClient::StartGame { .. } | InstructionFromClient::Rematch) == false {
+        return Err(eyre!("The game is over"));
+    }
+
+    match command {
+        InstructionFromClient::StartGame { .. } => {
+            if player_id != PlayerId::Player1 {
+                return Err(eyre!("Only {} can start the game", PlayerId::Player1));
+            }
+        }
+        InstructionFromClient::MoveToken { token: token_instance_id, .. } => {
+            let token = resources.token_instances.get(token_instance_id).context("Token to move not found")?;
+            if token.owner != player_id {
+                return Err(eyre!("Can't move a token owned by the other player"));
+            }
+        }
+        InstructionFromClient::PassTurn => {
+            if resources.current_turn != player_id {
+                return Err(eyre!("Can't pass the turn of the other player"));
+            }
+        }
+        InstructionFromClient::Concede => {
+            if resources.locations.is_empty() {
+                return Err(eyre!("There is no game to concede"));
+            }
+        }
+        InstructionFromClient::Rematch => {
+            if resources.game_over.is_none() {
+                return Err(eyre!("A rematch can only be asked for once the game is over"));
+            }
+        }
+        InstructionFromClient::Callback { .. } => {
+            if current_callback.as_ref().map_or(false, |callback| callback.is_owned_by(player_id) == false) {
+                return Err(eyre!("This prompt belongs to the other player"));
+            }
         }
+        _ => {}
     }
-}
\ No newline at end of file
+    Ok(())
+}
@@ -1,11 +1,12 @@
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 use std::num::ParseIntError;
+use serde::{Deserialize, Serialize};
 use crate::game::player::Player;
 
 pub type ServerInstanceId = u64;
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub struct LocationId(pub ServerInstanceId);
 
 pub mod location_ids {
@@ -101,6 +102,21 @@ pub mod location_ids {
             }
         }
 
+        pub fn is_hero(&self) -> bool {
+            match self {
+                LocationIdentity::Player1Hero | LocationIdentity::Player2Hero => true,
+                _ => false
+            }
+        }
+
+        /// Sets and hands, tokens in them are only known to their owner
+        pub fn is_private(&self) -> bool {
+            match self {
+                LocationIdentity::Player1Set | LocationIdentity::Player1Hand | LocationIdentity::Player2Set | LocationIdentity::Player2Hand => true,
+                _ => false
+            }
+        }
+
         pub fn is_item_slot(&self) -> bool {
             match self {
                 LocationIdentity::Player1ItemSlot | LocationIdentity::Player2ItemSlot => true,
@@ -152,7 +168,7 @@ impl FromStr for LocationId {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub struct TokenInstanceId(pub ServerInstanceId);
 
 impl FromStr for TokenInstanceId {
@@ -169,7 +185,7 @@ impl Display for TokenInstanceId {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
 pub struct PromptInstanceId(pub ServerInstanceId);
 
 impl FromStr for PromptInstanceId {
@@ -186,7 +202,7 @@ impl Display for PromptInstanceId {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PlayerId {
     Player1 = 0,
     Player2 = 1,
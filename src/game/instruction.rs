@@ -1,15 +1,19 @@
 use async_recursion::async_recursion;
 use color_eyre::Result;
+use serde_json::{json, Value};
 
 use crate::game::animation_presets::AnimationPreset;
 use crate::game::tokens::token_instance::TokenInstance;
 use crate::game::game_communicator::GameCommunicator;
+use crate::game::game_over::{GameOverReason, GameSummary};
 use crate::game::id_types::{TokenInstanceId, LocationId, PlayerId, PromptInstanceId, ServerInstanceId};
 use crate::game::player::Player;
 use crate::game::prompts::PromptType;
-use crate::game::tag::Tag;
+use crate::game::tag::{category_index, visible_counters, Tag};
+use crate::game::tokens::text_template;
+use crate::TOKEN_REGISTRY;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum InstructionToClient {
     AddLandscapeSlot {
         player_id: PlayerId,
@@ -52,6 +56,9 @@ pub enum InstructionToClient {
     UpdateBehaviors {
         token_data: TokenInstance,
     },
+    UpdateCounters {
+        token_data: TokenInstance,
+    },
     AddEquipmentSlot {
         token: TokenInstanceId,
         slot_location_id: LocationId
@@ -66,13 +73,45 @@ pub enum InstructionToClient {
         token: TokenInstanceId,
     },
     EndGame {
-        winner: PlayerId
-    }
+        winner: PlayerId,
+        reason: GameOverReason,
+        summary: GameSummary,
+    },
+    /// A player asked for a rematch after the game ended. It starts once both have asked.
+    RematchRequested {
+        player_id: PlayerId,
+    },
+    JoinSession {
+        session: String,
+        player_id: PlayerId,
+        /// Connecting to the session with this token again takes the seat back after a dropped connection.
+        rejoin_token: String,
+    },
+    /// Everything the client shows is about to be sent again, starting from an empty board.
+    Resync,
+    TypeNames {
+        types: Vec<(String, String)>,
+    },
+    Cancelled {
+        reason: String,
+    },
+    /// An action of the recipient was turned down and did not change the game.
+    Refused {
+        reason: String,
+    },
+    /// Everything up to the matching `EndBatch` belongs to one action and can be applied at once.
+    BeginBatch {
+        batch: u64,
+    },
+    EndBatch {
+        batch: u64,
+    },
 }
 
 impl InstructionToClient {
+    /// Builds the message as seen by the given player. Tokens that are hidden from them are sent as placeholders.
     #[async_recursion]
-    pub async fn build(self) -> Result<String> {
+    pub async fn build(self, recipient: PlayerId) -> Result<String> {
         Ok(match self {
             InstructionToClient::AddLandscapeSlot {
                 player_id,
@@ -112,7 +151,7 @@ impl InstructionToClient {
                 format!(
                     "create_token|{}{}{}{}{}",
                     Tag::U64(4).build()?,
-                    Tag::TokenInstanceData(token_data).build()?,
+                    Tag::visible_token_instance_data(token_data, recipient).build()?,
                     Tag::TokenInstanceId(instance_id).build()?,
                     Tag::Player(player_id).build()?,
                     Tag::LocationId(location_id).build()?,
@@ -141,10 +180,14 @@ impl InstructionToClient {
                 format!("remove_prompt|{}{}", Tag::U64(1).build()?, Tag::PromptInstanceId(prompt_instance_id).build()?)
             }
             InstructionToClient::UpdateData { token_data } => {
-                format!("update_data|{}{}{}", Tag::U64(2).build()?, Tag::TokenInstanceId(token_data.instance_id).build()?, Tag::TokenInstanceData(token_data).build()?)
+                format!("update_data|{}{}{}", Tag::U64(2).build()?, Tag::TokenInstanceId(token_data.instance_id).build()?, Tag::visible_token_instance_data(token_data, recipient).build()?)
             }
             InstructionToClient::UpdateBehaviors { token_data } => {
-                format!("update_behaviors|{}{}{}", Tag::U64(2).build()?, Tag::TokenInstanceId(token_data.instance_id).build()?, Tag::TokenBehaviors(token_data).build()?)
+                format!("update_behaviors|{}{}{}", Tag::U64(2).build()?, Tag::TokenInstanceId(token_data.instance_id).build()?, Tag::visible_token_behaviors(token_data, recipient).build()?)
+            }
+            InstructionToClient::UpdateCounters { token_data } => {
+                let counters = Tag::visible_token_counters(token_data.clone(), recipient, &TOKEN_REGISTRY.lock().await.counter_registry);
+                format!("update_counters|{}{}{}", Tag::U64(2).build()?, Tag::TokenInstanceId(token_data.instance_id).build()?, counters.build()?)
             }
             InstructionToClient::AddEquipmentSlot { token, slot_location_id } => {
                 format!("add_equipment_slot|{}{}{}", Tag::U64(2).build()?, Tag::TokenInstanceId(token).build()?, Tag::LocationId(slot_location_id).build()?)
@@ -155,10 +198,136 @@ impl InstructionToClient {
             InstructionToClient::Reveal { token } => {
                 format!("reveal|{}{}", Tag::U64(1).build()?, Tag::TokenInstanceId(token).build()?)
             }
-            InstructionToClient::EndGame { winner } => {
-                format!("end_game|{}{}", Tag::U64(1).build()?, Tag::Player(winner).build()?)
+            InstructionToClient::EndGame { winner, reason, summary } => {
+                format!("end_game|{}{}{}{}", Tag::U64(3).build()?, Tag::Player(winner).build()?, Tag::String(reason.to_string()).build()?, Tag::GameSummary(summary).build()?)
+            }
+            InstructionToClient::RematchRequested { player_id } => {
+                format!("rematch_requested|{}{}", Tag::U64(1).build()?, Tag::Player(player_id).build()?)
+            }
+            InstructionToClient::JoinSession { session, player_id, rejoin_token } => {
+                format!("join_session|{}{}{}{}", Tag::U64(3).build()?, Tag::String(session).build()?, Tag::Player(player_id).build()?, Tag::String(rejoin_token).build()?)
+            }
+            InstructionToClient::Resync => {
+                format!("resync|{}", Tag::U64(0).build()?)
+            }
+            InstructionToClient::TypeNames { types } => {
+                format!("type_names|{}{}", Tag::U64(1).build()?, Tag::TypeNames(types).build()?)
+            }
+            InstructionToClient::Cancelled { reason } => {
+                format!("cancelled|{}{}", Tag::U64(1).build()?, Tag::String(reason).build()?)
+            }
+            InstructionToClient::Refused { reason } => {
+                format!("refused|{}{}", Tag::U64(1).build()?, Tag::String(reason).build()?)
+            }
+            InstructionToClient::BeginBatch { batch } => {
+                format!("begin_batch|{}{}", Tag::U64(1).build()?, Tag::U64(batch).build()?)
+            }
+            InstructionToClient::EndBatch { batch } => {
+                format!("end_batch|{}{}", Tag::U64(1).build()?, Tag::U64(batch).build()?)
             }
             _ => todo!("instruction not implemented"),
         })
     }
+
+    /// Builds the message as a JSON object with a `type` field, hiding tokens the same way `build` does.
+    pub async fn build_json(self, recipient: PlayerId) -> Result<Value> {
+        Ok(match self {
+            InstructionToClient::AddLandscapeSlot { player_id, index, location_id } => {
+                json!({ "type": "add_slot", "player": player_id as u32, "index": index, "location": location_id })
+            }
+            InstructionToClient::SetThaum { player_id, amount } => {
+                json!({ "type": "set_thaum", "player": player_id as u32, "amount": amount })
+            }
+            InstructionToClient::MoveToken { token, to } => {
+                json!({ "type": "move_token", "token": token, "location": to })
+            }
+            InstructionToClient::CreateToken { token_data, instance_id, location_id, player_id } => {
+                json!({ "type": "create_token", "data": token_json(&token_data, recipient), "token": instance_id, "player": player_id as u32, "location": location_id })
+            }
+            InstructionToClient::PassTurn { player_id } => json!({ "type": "set_turn", "player": player_id as u32 }),
+            InstructionToClient::ClearLocation { location } => json!({ "type": "clear_location", "location": location }),
+            InstructionToClient::Destroy { token } => json!({ "type": "destroy", "token": token }),
+            InstructionToClient::AddPrompt { prompt_instance_id, prompt_type } => {
+                let target = match prompt_type {
+                    PromptType::SelectToken(token_id) | PromptType::AttackToken(token_id) => token_id.0,
+                    PromptType::SelectFieldSlot(location_id) => location_id.0,
+                };
+                json!({ "type": "add_prompt", "prompt": prompt_instance_id, "target": target, "prompt_type": prompt_type.to_string() })
+            }
+            InstructionToClient::RemovePrompt { prompt_instance_id } => json!({ "type": "remove_prompt", "prompt": prompt_instance_id }),
+            InstructionToClient::UpdateData { token_data } => {
+                json!({ "type": "update_data", "token": token_data.instance_id, "data": token_json(&token_data, recipient) })
+            }
+            InstructionToClient::UpdateBehaviors { token_data } => {
+                let behaviors = match token_data.is_visible_to(recipient) {
+                    true => token_data.behaviors.iter()
+                        .filter_map(|behavior| behavior.name.as_ref().map(|name| json!({
+                            "name": name,
+                            "description": text_template::expand_stats(behavior.description.as_deref().unwrap_or(""), token_data.cost, &token_data.current_stats),
+                        })))
+                        .collect::<Vec<Value>>(),
+                    false => Vec::new(),
+                };
+                json!({ "type": "update_behaviors", "token": token_data.instance_id, "behaviors": behaviors })
+            }
+            InstructionToClient::UpdateCounters { token_data } => {
+                let counters = visible_counters(&token_data, recipient, &TOKEN_REGISTRY.lock().await.counter_registry).into_iter()
+                    .map(|(id, name, value)| json!({ "id": id, "name": name, "value": value }))
+                    .collect::<Vec<Value>>();
+                json!({ "type": "update_counters", "token": token_data.instance_id, "counters": counters })
+            }
+            InstructionToClient::AddEquipmentSlot { token, slot_location_id } => {
+                json!({ "type": "add_equipment_slot", "token": token, "location": slot_location_id })
+            }
+            InstructionToClient::Animate { token, location, duration, preset } => {
+                json!({ "type": "animate", "token": token, "location": location, "duration": duration, "preset": preset.to_string() })
+            }
+            InstructionToClient::Reveal { token } => json!({ "type": "reveal", "token": token }),
+            InstructionToClient::EndGame { winner, reason, summary } => {
+                let players = summary.players.iter().map(|player| json!({
+                    "player": player.player_id as u32,
+                    "hero_health": player.hero_health,
+                    "tokens_in_set": player.tokens_in_set,
+                    "tokens_in_hand": player.tokens_in_hand,
+                    "tokens_in_graveyard": player.tokens_in_graveyard,
+                })).collect::<Vec<Value>>();
+                json!({ "type": "end_game", "winner": winner as u32, "reason": reason, "rounds": summary.rounds, "seed": summary.seed, "players": players })
+            }
+            InstructionToClient::RematchRequested { player_id } => json!({ "type": "rematch_requested", "player": player_id as u32 }),
+            InstructionToClient::JoinSession { session, player_id, rejoin_token } => {
+                json!({ "type": "join_session", "session": session, "player": player_id as u32, "rejoin_token": rejoin_token })
+            }
+            InstructionToClient::Resync => json!({ "type": "resync" }),
+            InstructionToClient::TypeNames { types } => {
+                let types = types.into_iter().map(|(id, name)| json!({ "id": id, "name": name })).collect::<Vec<Value>>();
+                json!({ "type": "type_names", "types": types })
+            }
+            InstructionToClient::Cancelled { reason } => json!({ "type": "cancelled", "reason": reason }),
+            InstructionToClient::Refused { reason } => json!({ "type": "refused", "reason": reason }),
+            InstructionToClient::BeginBatch { batch } => json!({ "type": "begin_batch", "batch": batch }),
+            InstructionToClient::EndBatch { batch } => json!({ "type": "end_batch", "batch": batch }),
+        })
+    }
+}
+
+/// The fields of a token as the recipient may see them. Hidden tokens only show their category.
+fn token_json(token: &TokenInstance, recipient: PlayerId) -> Value {
+    let category = category_index(&token.token_data.token_category);
+    if token.is_visible_to(recipient) == false {
+        return json!({ "hidden": true, "category": category });
+    }
+
+    let description = token.token_data.description.as_deref().unwrap_or("");
+    json!({
+        "hidden": false,
+        "id": token.token_data.id,
+        "category": category,
+        "name": token.token_data.name,
+        "description": text_template::expand_stats(description, token.cost, &token.current_stats),
+        "cost": token.cost,
+        "health": token.current_stats.health,
+        "defense": token.current_stats.defense,
+        "attack": token.current_stats.attack,
+        "types": token.token_types,
+    })
 }
@@ -1,4 +1,5 @@
 use color_eyre::Result;
+use crate::game::game_rng::GameRng;
 use crate::game::id_types::{TokenInstanceId, LocationId, ServerInstanceId};
 
 pub trait Location {
@@ -8,7 +9,7 @@ pub trait Location {
     fn add_token(&mut self, token: TokenInstanceId) -> Result<()>;
     fn remove_token(&mut self, token: TokenInstanceId);
     fn clear(&mut self);
-    fn shuffle(&mut self);
+    fn shuffle(&mut self, rng: &GameRng);
     
     fn contains(&self, token: TokenInstanceId) -> bool;
     fn has_room(&self) -> bool;
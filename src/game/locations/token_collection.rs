@@ -1,5 +1,6 @@
 use color_eyre::Result;
 
+use crate::game::game_rng::GameRng;
 use crate::game::id_types::{TokenInstanceId, LocationId, ServerInstanceId};
 use crate::game::locations::location::Location;
 
@@ -30,10 +31,10 @@ impl Location for TokenCollection {
         self.tokens.clear()
     }
 
-    fn shuffle(&mut self) {
+    fn shuffle(&mut self, rng: &GameRng) {
         let mut new_tokens = Vec::new();
         while self.tokens.len() > 0{
-            new_tokens.push(self.tokens.remove(fastrand::usize(0..self.tokens.len())));
+            new_tokens.push(self.tokens.remove(rng.usize(0..self.tokens.len())));
         }
         self.tokens = new_tokens;
     }
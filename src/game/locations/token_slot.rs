@@ -1,6 +1,7 @@
 use color_eyre::eyre::eyre;
 
 use color_eyre::Result;
+use crate::game::game_rng::GameRng;
 use crate::game::id_types::{TokenInstanceId, LocationId, ServerInstanceId};
 use crate::game::locations::location::Location;
 
@@ -44,7 +45,7 @@ impl Location for TokenSlot {
         self.token = None;
     }
 
-    fn shuffle(&mut self) {
+    fn shuffle(&mut self, _rng: &GameRng) {
     }
 
     fn contains(&self, token: TokenInstanceId) -> bool { Some(token) == self.token }
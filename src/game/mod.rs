@@ -1,14 +1,24 @@
 ﻿pub mod board;
 pub mod tokens;
 pub mod game_communicator;
+pub mod game_lobby;
 pub mod game_service;
 pub mod instruction;
 pub mod locations;
 pub mod player;
 pub mod tag;
 pub mod game_context;
+pub mod game_rng;
 pub mod prompts;
 pub mod state_resources;
 pub mod id_types;
 pub mod animation_presets;
-pub mod new_state_machine;
\ No newline at end of file
+pub mod new_state_machine;
+pub mod output_sink;
+pub mod protocol;
+pub mod replay;
+pub mod snapshot;
+pub mod game_over;
+
+#[cfg(test)]
+mod tests;
\ No newline at end of file
@@ -1,10 +1,13 @@
 use std::collections::VecDeque;
-use color_eyre::eyre::{ContextCompat, eyre};
-use crate::game::tokens::token_deserializer::{TokenBehaviorTriggerWhenName as TriggerState, TokenBehaviorTriggerWhenName};
+use color_eyre::eyre::{Context, ContextCompat, eyre};
+use crate::game::tokens::token_deserializer::{SelectionIntention, TokenCategory, TokenBehaviorTriggerWhenName as TriggerState, TokenBehaviorTriggerWhenName};
 use crate::game::game_communicator::GameCommunicator;
+use crate::game::game_over::GameOverReason;
+use crate::game::game_rng::GameRng;
+use crate::game::replay::ReplayEvent;
 use crate::game::id_types::{location_ids, LocationId, TokenInstanceId};
 use crate::game::id_types::PlayerId;
-use crate::game::prompts::PromptCallback;
+use crate::game::prompts::{PromptCallback, PromptCallbackResult, PromptInstance, PromptKind, PromptProfile, PromptType};
 use crate::game::state_resources::{StateResources, ThreadSafeLocation};
 use crate::game::tag::get_tag;
 use crate::game::game_context::{GameContext, ContextValue, context_keys};
@@ -17,6 +20,7 @@ use crate::game::instruction::InstructionToClient;
 use crate::game::locations::token_collection::TokenCollection;
 use crate::game::locations::token_slot::TokenSlot;
 use crate::game::player::Player;
+use crate::TOKEN_REGISTRY;
 
 pub type TokenBehaviorTriggerWithContext<'a> = (TriggerState, &'a mut GameContext);
 
@@ -31,15 +35,28 @@ impl StateMachine {
         }
     }
     
+    /// Runs the queued groups until they are done or wait for a prompt. Everything they send goes out as one batch.
     pub async fn process(&mut self, resources: &mut StateResources, communicator: &mut GameCommunicator) -> Result<Option<PromptCallback>> {
+        communicator.begin_batch();
+        let result = self.process_groups(resources, communicator).await;
+        communicator.end_batch().await?;
+        result
+    }
+
+    async fn process_groups(&mut self, resources: &mut StateResources, communicator: &mut GameCommunicator) -> Result<Option<PromptCallback>> {
         while let Some(mut next) = self.state_transition_groups.pop_front() {
-            while next.queue_empty() == false {
+            // Whatever is still queued once the game has been decided is dropped
+            while next.queue_empty() == false && resources.game_over.is_none() {
                 match next.process(self, resources, communicator).await? {
                     TriggerResult::ReadPrompt(mut prompt) => {
                         prompt.context = next.context.clone();
                         self.state_transition_groups.push_front(next);
                         return Ok(Some(prompt))
                     }
+                    TriggerResult::TerminateGroup => {
+                        next.terminate(resources, communicator).await?;
+                        break;
+                    }
                     _ => {}
                 }
             }
@@ -53,7 +70,16 @@ impl StateMachine {
 }
 
 impl StateMachine {
-    pub async fn start_game(mut self: &mut Self, data: &str, resources: &mut StateResources, communicator: &mut GameCommunicator) -> Result<()> {
+    pub async fn start_game(mut self: &mut Self, set_1: &[String], set_2: &[String], seed: Option<u64>, resources: &mut StateResources, communicator: &mut GameCommunicator) -> Result<()> {
+        // Games started with the same seed and played the same way turn out the same
+        let seed = seed.unwrap_or_else(|| GameRng::from_entropy().seed);
+        println!("Starting game with seed {}", seed);
+        resources.seed(seed);
+        communicator.record(ReplayEvent::Seed { seed });
+        resources.round = 0;
+        resources.game_over = None;
+        resources.sets = (set_1.to_vec(), set_2.to_vec());
+
         let mut insert_location = |location: Box<ThreadSafeLocation>| {
             resources.locations.insert(location.get_location_id(), location);
         };
@@ -70,11 +96,12 @@ impl StateMachine {
 
         resources.reset_game(communicator).await?;
 
+        let types = TOKEN_REGISTRY.lock().await.type_registry.get_type_names();
+        communicator.send_game_instruction(InstructionToClient::TypeNames { types }).await?;
+
         // Populate sets
-        let set_1_string = get_tag("set1", data)?;
-        let set_2_string = get_tag("set2", data)?;
-        Player::populate_set(PlayerId::Player1, &set_1_string[..], resources, communicator).await?;
-        Player::populate_set(PlayerId::Player2, &set_2_string[..], resources, communicator).await?;
+        Player::populate_set(PlayerId::Player1, set_1, resources, communicator).await?;
+        Player::populate_set(PlayerId::Player2, set_2, resources, communicator).await?;
 
         Player::set_thaum(PlayerId::Player1, resources, 0, communicator).await?;
         Player::prepare_set(PlayerId::Player1, resources, communicator).await?;
@@ -89,11 +116,32 @@ impl StateMachine {
             self.draw_token(PlayerId::Player2);
         }
 
-        resources.set_current_turn(resources.current_turn, self, communicator).await?;
+        self.start_turn(resources.current_turn);
 
         Ok(())
     }
 
+    /// Ends the turn of the given player and starts the turn of their opponent.
+    pub fn pass_turn(&mut self, player: PlayerId) {
+        self.end_turn(player);
+        self.start_turn(player.opponent());
+    }
+
+    /// Turn boundaries wait for everything that is already queued.
+    pub fn start_turn(&mut self, player: PlayerId) {
+        let mut transition_group = StateTransitionGroup::new();
+        transition_group.context.insert(context_keys::PLAYER, ContextValue::PlayerId(player));
+        transition_group.states.push_back(TriggerState::TurnStarted);
+        self.state_transition_groups.push_back(transition_group);
+    }
+
+    pub fn end_turn(&mut self, player: PlayerId) {
+        let mut transition_group = StateTransitionGroup::new();
+        transition_group.context.insert(context_keys::PLAYER, ContextValue::PlayerId(player));
+        transition_group.states.push_back(TriggerState::TurnEnded);
+        self.state_transition_groups.push_back(transition_group);
+    }
+
     pub fn move_token(&mut self, token_instance_id: TokenInstanceId, target_location: LocationId) {
         let mut transition_group = StateTransitionGroup::new();
 
@@ -112,6 +160,7 @@ impl StateMachine {
         transition_group.context.insert(context_keys::TO_LOCATION, ContextValue::LocationId(target_location));
         transition_group.states.push_back(TriggerState::WillBeMoved);
         transition_group.states.push_back(TriggerState::WillBeSummoned);
+        transition_group.states.push_back(TriggerState::CheckCancel);
         transition_group.states.push_back(TriggerState::HasBeenMoved);
         transition_group.states.push_back(TriggerState::HasBeenSummoned);
         self.state_transition_groups.push_front(transition_group);
@@ -122,6 +171,11 @@ impl StateMachine {
         transition_group.context.insert(context_keys::ATTACKER, ContextValue::TokenInstanceId(attacker));
         transition_group.context.insert(context_keys::DEFENDER, ContextValue::TokenInstanceId(defender));
         transition_group.context.insert(context_keys::IS_COUNTER_ATTACK, ContextValue::Bool(is_counter_attack));
+        if is_counter_attack == false {
+            // Counter attacks hit back at the attacker, they don't select it
+            transition_group.context.insert(context_keys::SELECTION_INTENTION, ContextValue::String(SelectionIntention::Attack.as_str().to_string()));
+            transition_group.states.push_back(TriggerState::WillBeSelected);
+        }
         transition_group.states.push_back(TriggerState::WillAttack);
         transition_group.states.push_back(TriggerState::WillBeAttacked);
         transition_group.states.push_back(TriggerState::CheckCancel);
@@ -140,6 +194,7 @@ impl StateMachine {
         transition_group.context.insert(context_keys::DEFENDER, ContextValue::TokenInstanceId(defender));
         transition_group.context.insert(context_keys::EFFECT_DAMAGE, ContextValue::I64(amount as i64));
         transition_group.states.push_back(TriggerState::WillBeEffectDamaged);
+        transition_group.states.push_back(TriggerState::CheckCancel);
         transition_group.states.push_back(TriggerState::HasBeenEffectDamaged);
         self.state_transition_groups.push_front(transition_group);
     }
@@ -174,6 +229,33 @@ impl StateMachine {
         transition_group.context.insert(context_keys::PLAYER, ContextValue::PlayerId(owner));
         transition_group.context.insert(context_keys::TO_LOCATION, ContextValue::LocationId(location));
         transition_group.states.push_back(TriggerState::HasBeenCreated);
+        transition_group.states.push_back(TriggerState::CheckCancel);
+        self.state_transition_groups.push_front(transition_group);
+    }
+
+    /// Creates a new unit directly on the field and summons it, as opposed to summoning one from the hand.
+    pub fn summon_new_token(&mut self, token_id: &str, owner: PlayerId, location: LocationId) {
+        let mut transition_group = StateTransitionGroup::new();
+        transition_group.context.insert(context_keys::CREATING_TOKEN, ContextValue::String(token_id.to_string()));
+        transition_group.context.insert(context_keys::PLAYER, ContextValue::PlayerId(owner));
+        transition_group.context.insert(context_keys::TO_LOCATION, ContextValue::LocationId(location));
+        // Units an effect brings in are free, only units played from hand are paid for
+        transition_group.context.insert(context_keys::IS_EFFECT_SUMMON, ContextValue::Bool(true));
+        transition_group.states.push_back(TriggerState::HasBeenCreated);
+        transition_group.states.push_back(TriggerState::WillBeSummoned);
+        transition_group.states.push_back(TriggerState::CheckCancel);
+        transition_group.states.push_back(TriggerState::HasBeenSummoned);
+        self.state_transition_groups.push_front(transition_group);
+    }
+
+    /// Lets the owner pick the target of a command if it needs one, then casts it.
+    pub fn cast_command(&mut self, command: TokenInstanceId) {
+        let mut transition_group = StateTransitionGroup::new();
+        transition_group.context.insert(context_keys::CAST_TOKEN, ContextValue::TokenInstanceId(command));
+        transition_group.states.push_back(TriggerState::SelectCastTarget);
+        transition_group.states.push_back(TriggerState::WillCast);
+        transition_group.states.push_back(TriggerState::CheckCancel);
+        transition_group.states.push_back(TriggerState::HasCast);
         self.state_transition_groups.push_front(transition_group);
     }
 
@@ -225,7 +307,72 @@ impl StateTransitionGroup {
         let result = Ok(match next {
             TriggerState::CheckCancel => {
                 let cancel = self.context.get(context_keys::CANCEL).map_or(false, |v| v.as_bool().unwrap());
-                if cancel { TriggerResult::TerminateGroup } else { TriggerResult::Ok }
+                if cancel {
+                    // A token the group already created has nowhere to go back to
+                    if let Ok(ContextValue::TokenInstanceId(created)) = self.context.get(context_keys::CREATING_TOKEN) {
+                        resources.destroy_token(*created, communicator).await?;
+                    }
+                    TriggerResult::TerminateGroup
+                } else {
+                    TriggerResult::Ok
+                }
+            }
+
+            TriggerState::SelectCastTarget => {
+                let command_id = self.context.get(context_keys::CAST_TOKEN)?.as_token_instance_id()?;
+                let command = resources.token_instances.get(&command_id).context("Unable to find command to cast")?;
+                let owner = command.owner;
+                match &command.token_data.token_category {
+                    TokenCategory::Command { cast_target: Some(filter) } => {
+                        let mut filter_context = self.context.clone();
+                        filter_context.insert(context_keys::OWNER, ContextValue::PlayerId(owner));
+                        filter_context.insert(context_keys::ACTION_THIS, ContextValue::TokenInstanceId(command_id));
+                        let mut targets = resources.get_tokens_on_field();
+                        filter.evaluate(&mut targets, &filter_context, resources)?;
+
+                        if targets.is_empty() {
+                            self.context.insert(context_keys::CANCEL, ContextValue::Bool(true));
+                            self.context.insert(context_keys::CANCEL_REASON, ContextValue::String("Command has no valid targets".to_string()));
+                            TriggerResult::TerminateGroup
+                        } else {
+                            let mut callback = PromptCallback::new(PromptKind::SelectCastTarget, false);
+                            for target in targets {
+                                callback.add_prompt(PromptProfile {
+                                    prompt_type: PromptType::SelectToken(target.instance_id),
+                                    value: false,
+                                    owner,
+                                }, &resources.rng);
+                            }
+                            TriggerResult::ReadPrompt(callback)
+                        }
+                    }
+                    _ => TriggerResult::Ok
+                }
+            }
+            TriggerState::WillCast => {
+                let command_id = self.context.get(context_keys::CAST_TOKEN)?.as_token_instance_id()?;
+                resources.reveal_token(command_id, communicator).await?;
+                TriggerResult::Ok
+            }
+            TriggerState::HasCast => {
+                let command_id = self.context.get(context_keys::CAST_TOKEN)?.as_token_instance_id()?;
+                let command = resources.token_instances.get(&command_id).context("Unable to find command to cast")?;
+                let owner = command.owner;
+                let cost = command.cost;
+                let graveyard = resources.board.get_side(owner).graveyard;
+                Player::spend_thaum(owner, resources, cost, communicator).await?;
+                // Commands are spent once cast, their own has_cast behaviors fire from the graveyard
+                resources.move_token(command_id, graveyard, Some(AnimationPreset::EaseInOut), communicator).await?;
+                TriggerResult::Ok
+            }
+
+            TriggerState::TurnStarted => {
+                let player = self.context.get(context_keys::PLAYER)?.as_player_id()?;
+                resources.set_current_turn(player, state, communicator).await?;
+                TriggerResult::Ok
+            }
+            TriggerState::TurnEnded => {
+                TriggerResult::Ok
             }
 
             TriggerState::HasBeenCreated => {
@@ -234,6 +381,7 @@ impl StateTransitionGroup {
                 let owner = self.context.get(context_keys::PLAYER)?.as_player_id()?;
                 let instance_id = resources.create_token(token_id, location, owner, communicator).await?;
                 self.context.insert(context_keys::CREATING_TOKEN, ContextValue::TokenInstanceId(instance_id));
+                self.context.insert(context_keys::TOKEN_INSTANCE, ContextValue::TokenInstanceId(instance_id));
                 TriggerResult::Ok
             }
 
@@ -254,10 +402,14 @@ impl StateTransitionGroup {
             TriggerState::HasBeenSummoned => {
                 let token = resources.token_instances.get(&self.context.get(context_keys::TOKEN_INSTANCE)?.as_token_instance_id()?).unwrap();
                 let cost = token.cost;
+                let owner = token.owner;
                 let token_instance_id = token.instance_id;
-                communicator.send_game_instruction(InstructionToClient::Reveal { token: token_instance_id }).await?;
-                Player::spend_thaum(token.owner, resources, token.cost, communicator).await?;
+                resources.reveal_token(token_instance_id, communicator).await?;
+                if self.context.get(context_keys::IS_EFFECT_SUMMON).map_or(false, |v| v.as_bool().unwrap()) == false {
+                    Player::spend_thaum(owner, resources, cost, communicator).await?;
+                }
                 resources.add_equipment_slot(token_instance_id, communicator).await?;
+                resources.apply_summoning_sickness(token_instance_id, communicator).await?;
                 TriggerResult::Ok
             }
 
@@ -267,6 +419,9 @@ impl StateTransitionGroup {
             TriggerState::WillBeAttacked => {
                 TriggerResult::Ok
             }
+            TriggerState::WillBeSelected => {
+                TriggerResult::Ok
+            }
             TriggerState::HasAttacked => {
                 let attacker_id = self.context.get(context_keys::ATTACKER)?.as_token_instance_id()?;
                 let defender_id = self.context.get(context_keys::DEFENDER)?.as_token_instance_id()?;
@@ -277,6 +432,10 @@ impl StateTransitionGroup {
                     duration: 0.5,
                     preset: AnimationPreset::Attack,
                 }).await?;
+                // Hitting back doesn't use up an attack
+                if self.context.get(context_keys::IS_COUNTER_ATTACK).map_or(false, |v| v.as_bool().unwrap()) == false {
+                    resources.spend_attack(attacker_id, communicator).await?;
+                }
                 TriggerResult::Ok
             }
             TriggerState::HasBeenAttacked => {
@@ -341,8 +500,7 @@ impl StateTransitionGroup {
 
                 match token {
                     None => {
-                        communicator.send_game_instruction(InstructionToClient::EndGame { winner: player_id.opponent() }).await?;
-                        return Err(eyre!("Ran out of tokens, game concluded"))
+                        resources.end_game(player_id.opponent(), GameOverReason::DeckOut, communicator).await?;
                     }
                     Some(token_key) => {
                         resources.move_token(token_key, player_hand, None, communicator).await?;
@@ -375,6 +533,11 @@ impl StateTransitionGroup {
                     }
                 }
 
+                // Items equipped to a hidden unit stay hidden until the unit is revealed
+                if resources.token_instances.get(&unit).context("Unable to find unit to equip to")?.hidden == false {
+                    resources.reveal_token(item, communicator).await?;
+                }
+
                 TriggerResult::Ok
             }
             TriggerState::HasEquipped => {
@@ -383,7 +546,7 @@ impl StateTransitionGroup {
             _ => TriggerResult::Ok
         });
 
-        if matches!(next, TriggerState::CheckCancel) { return result }
+        if matches!(next, TriggerState::CheckCancel | TriggerState::SelectCastTarget) { return result }
 
         if let Ok(this) = what_is_this(next.clone()) {
             let this_context_value = self.context.get(&this)?.clone();
@@ -392,7 +555,18 @@ impl StateTransitionGroup {
         } else {
             self.context.insert(context_keys::OWNER, self.context.get(&*who_is_owner(next.clone())?)?.clone());
         }
-        for token_id in resources.board.get_tokens_in_play(resources) {
+        let defender = self.context.get(context_keys::DEFENDER).ok().cloned();
+
+        // Tokens in hands and sets only react to triggers that are live there, see TokenBehaviorTrigger::is_live_in
+        let mut tokens = resources.board.get_tokens_in_play(resources);
+        for player_id in [PlayerId::Player1, PlayerId::Player2] {
+            let player = resources.get_player(player_id);
+            for location in [player.hand, player.set] {
+                tokens.append(&mut resources.locations.get(&location).context("Player location does not exist")?.get_tokens().clone());
+            }
+        }
+
+        for token_id in tokens {
             // Process item triggers first
             let items = resources.token_instances.get(&token_id).unwrap().equipment_slots
                 .iter()
@@ -421,9 +595,42 @@ impl StateTransitionGroup {
                 communicator).await?;
         }
 
+        // An attack redirected to another defender has to be able to select it, a Protected unit cancels it
+        let redirected = self.context.get(context_keys::DEFENDER).ok() != defender.as_ref();
+        if next == TriggerState::WillBeAttacked && redirected && self.context.get(context_keys::SELECTION_INTENTION).is_ok() {
+            self.states.push_front(TriggerState::WillBeSelected);
+        }
+
         return result;
     }
 
+    pub fn on_cast_target_selected(prompt: PromptInstance, context: &mut GameContext, state: &mut StateMachine, resources: &mut StateResources, communicator: &mut GameCommunicator) -> Result<PromptCallbackResult> {
+        match prompt.prompt {
+            PromptType::SelectToken(token_instance_id) => {
+                context.insert(context_keys::CAST_TARGET, ContextValue::TokenInstanceId(token_instance_id));
+                state.update_current_context(context.clone());
+                Ok(PromptCallbackResult::End(None))
+            }
+            _ => Ok(PromptCallbackResult::Keep)
+        }
+    }
+
+    /// Puts tokens a player already dragged on their client back where they are and tells both players why the group ended.
+    async fn terminate(&self, resources: &StateResources, communicator: &mut GameCommunicator) -> Result<()> {
+        for key in [context_keys::TOKEN_INSTANCE, context_keys::EQUIPPING_ITEM, context_keys::CAST_TOKEN] {
+            let Ok(value) = self.context.get(key) else { continue };
+            let token = value.as_token_instance_id()?;
+            if let Some(token_instance) = resources.token_instances.get(&token) {
+                communicator.send_game_instruction(InstructionToClient::MoveToken { token, to: token_instance.location }).await?;
+            }
+        }
+
+        let reason = self.context.get(context_keys::CANCEL_REASON)
+            .and_then(|reason| reason.as_string().cloned())
+            .unwrap_or("Cancelled".to_string());
+        communicator.send_game_instruction(InstructionToClient::Cancelled { reason }).await
+    }
+
     pub fn queue_empty(&self) -> bool {
         self.states.is_empty()
     }
@@ -441,6 +648,7 @@ fn what_is_this(state: TriggerState) -> Result<String> {
         TriggerState::HasBeenSummoned => context_keys::TOKEN_INSTANCE,
         TriggerState::WillAttack => context_keys::ATTACKER,
         TriggerState::WillBeAttacked => context_keys::DEFENDER,
+        TriggerState::WillBeSelected => context_keys::DEFENDER,
         TriggerState::HasAttacked => context_keys::ATTACKER,
         TriggerState::HasBeenAttacked => context_keys::DEFENDER,
         TriggerState::TookDamage => context_keys::DEFENDER,
@@ -460,6 +668,8 @@ fn what_is_this(state: TriggerState) -> Result<String> {
         TriggerState::HasBeenEquipped => context_keys::EQUIPPING_ITEM,
         TriggerState::HasBeenDrawn => context_keys::DRAWN_TOKEN,
         TriggerState::HasBeenCreated => context_keys::CREATING_TOKEN,
+        TriggerState::WillCast => context_keys::CAST_TOKEN,
+        TriggerState::HasCast => context_keys::CAST_TOKEN,
         _ => return Err(eyre!("Can't specify what this is for state: {state:?}")),
     }.to_string())
 }
@@ -468,6 +678,52 @@ fn who_is_owner(state: TriggerState) -> Result<String> {
     Ok(match state {
         TriggerState::WillDrawToken => context_keys::PLAYER,
         TriggerState::HasDrawnToken => context_keys::PLAYER,
+        TriggerState::TurnStarted => context_keys::PLAYER,
+        TriggerState::TurnEnded => context_keys::PLAYER,
         _ => return Err(eyre!("Can't specify what owner is for state: {state:?}")),
     }.to_string())
-}
\ No newline at end of file
+}
+
+/// The context keys every behavior triggered by the given state can read, or None if no transition group fires the state.
+/// Keys only some of the groups firing a state provide are left out.
+pub fn supplied_context_keys(state: TriggerState) -> Option<Vec<&'static str>> {
+    let mut keys = match state {
+        TriggerState::WillBeMoved
+        | TriggerState::HasBeenMoved
+        | TriggerState::WillBeSummoned
+        | TriggerState::HasBeenSummoned => vec!(context_keys::TOKEN_INSTANCE, context_keys::TO_LOCATION),
+        TriggerState::WillBeSelected => vec!(context_keys::ATTACKER, context_keys::DEFENDER, context_keys::IS_COUNTER_ATTACK, context_keys::SELECTION_INTENTION),
+        TriggerState::WillAttack
+        | TriggerState::WillBeAttacked
+        | TriggerState::HasAttacked
+        | TriggerState::HasBeenAttacked => vec!(context_keys::ATTACKER, context_keys::DEFENDER, context_keys::IS_COUNTER_ATTACK),
+        TriggerState::WillBeEffectDamaged
+        | TriggerState::HasBeenEffectDamaged => vec!(context_keys::ATTACKER, context_keys::DEFENDER, context_keys::EFFECT_DAMAGE),
+        TriggerState::WillDefeat
+        | TriggerState::WillBeDefeated
+        | TriggerState::HasDefeated
+        | TriggerState::HasBeenDefeated
+        | TriggerState::WillBeDestroyed
+        | TriggerState::HasBeenDestroyed => vec!(context_keys::ATTACKER, context_keys::DEFENDER),
+        TriggerState::HasBeenCreated => vec!(context_keys::CREATING_TOKEN, context_keys::TOKEN_INSTANCE, context_keys::PLAYER, context_keys::TO_LOCATION),
+        TriggerState::WillDrawToken
+        | TriggerState::HasDrawnToken
+        | TriggerState::TurnStarted
+        | TriggerState::TurnEnded => vec!(context_keys::PLAYER),
+        TriggerState::HasBeenDrawn => vec!(context_keys::PLAYER, context_keys::DRAWN_TOKEN),
+        TriggerState::WillCast
+        | TriggerState::HasCast => vec!(context_keys::CAST_TOKEN),
+        TriggerState::WillBeEquipped
+        | TriggerState::WillEquip
+        | TriggerState::HasBeenEquipped
+        | TriggerState::HasEquipped => vec!(context_keys::EQUIP_TARGET, context_keys::EQUIPPING_ITEM),
+        _ => return None,
+    };
+
+    keys.push(context_keys::OWNER);
+    keys.push(context_keys::ACTION_THIS);
+    if what_is_this(state).is_ok() {
+        keys.push(context_keys::TRIGGER_THIS);
+    }
+    Some(keys)
+}
@@ -0,0 +1,134 @@
+use std::sync::{Arc, Mutex};
+
+use color_eyre::Result;
+use futures_util::future::BoxFuture;
+use futures_util::SinkExt;
+use futures_util::stream::SplitSink;
+use owo_colors::{OwoColorize, Rgb, Style};
+use serde_json::json;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::game::id_types::PlayerId;
+use crate::game::instruction::InstructionToClient;
+use crate::game::protocol::WireFormat;
+
+#[derive(Clone, Debug)]
+pub enum OutgoingMessage {
+    Instruction(InstructionToClient),
+    Info(String),
+    Warning(String),
+    Error(String),
+    Raw(String),
+}
+
+impl OutgoingMessage {
+    /// The text a seat receives over the wire.
+    pub async fn build(&self, recipient: PlayerId) -> Result<String> {
+        Ok(match self {
+            OutgoingMessage::Instruction(instruction) => instruction.clone().build(recipient).await?,
+            OutgoingMessage::Info(info) => format!("info|{}", info),
+            OutgoingMessage::Warning(warning) => format!("warn|{}", warning),
+            OutgoingMessage::Error(error) => format!("error|{}", error),
+            OutgoingMessage::Raw(msg) => msg.clone(),
+        })
+    }
+
+    /// The same message as a JSON object, which is never ambiguous about where a value ends.
+    pub async fn build_json(&self, recipient: PlayerId) -> Result<String> {
+        let value = match self {
+            OutgoingMessage::Instruction(instruction) => instruction.clone().build_json(recipient).await?,
+            OutgoingMessage::Info(info) => json!({ "type": "info", "message": info }),
+            OutgoingMessage::Warning(warning) => json!({ "type": "warn", "message": warning }),
+            OutgoingMessage::Error(error) => json!({ "type": "error", "message": error }),
+            OutgoingMessage::Raw(msg) => json!({ "type": "raw", "message": msg }),
+        };
+        Ok(value.to_string())
+    }
+
+    pub async fn build_as(&self, format: WireFormat, recipient: PlayerId) -> Result<String> {
+        match format {
+            WireFormat::Legacy => self.build(recipient).await,
+            WireFormat::Json => self.build_json(recipient).await,
+        }
+    }
+}
+
+/// Receives everything the engine produces for one seat. The engine never talks to a transport directly,
+/// so a game can be played over websockets or entirely in memory.
+pub trait OutputSink: Send {
+    fn send(&mut self, recipient: PlayerId, message: OutgoingMessage) -> BoxFuture<'_, Result<()>>;
+}
+
+pub struct WebSocketOutput {
+    sink: SplitSink<WebSocketStream<TcpStream>, Message>,
+    format: WireFormat,
+    server_style: Style,
+}
+
+impl WebSocketOutput {
+    pub fn new(sink: SplitSink<WebSocketStream<TcpStream>, Message>, format: WireFormat) -> Self {
+        Self {
+            sink,
+            format,
+            server_style: Style::new().color(Rgb(50, 150, 200)).bold(),
+        }
+    }
+}
+
+impl OutputSink for WebSocketOutput {
+    fn send(&mut self, recipient: PlayerId, message: OutgoingMessage) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let server = "(Server)".style(self.server_style);
+            let text = message.build_as(self.format, recipient).await?;
+            match message {
+                OutgoingMessage::Instruction(_) => println!("{} {} {}: {}", server, "Command".color(Rgb(80, 150, 120)), format!("[{}]", recipient).color(Rgb(150, 150, 150)), text.color(Rgb(120, 120, 120))),
+                OutgoingMessage::Info(info) => println!("{} {}: {}", server, "Info".color(Rgb(150, 150, 150)), info),
+                OutgoingMessage::Warning(warning) => println!("{} {}: {}", server, "Warning".color(Rgb(250, 200, 30)), warning),
+                OutgoingMessage::Error(error) => println!("{} {}: {}", server, "Error".color(Rgb(255, 50, 50)), error),
+                OutgoingMessage::Raw(_) => println!("{} {}: {}", server, "Raw".color(Rgb(80, 150, 120)), text.color(Rgb(120, 120, 120))),
+            }
+            self.sink.send(Message::Text(text)).await?;
+            Ok(())
+        })
+    }
+}
+
+/// Collects the output of a game in memory. Clones share the same log, so one instance can serve both seats.
+#[derive(Clone, Default)]
+pub struct MemoryOutput {
+    messages: Arc<Mutex<Vec<(PlayerId, OutgoingMessage)>>>,
+}
+
+impl MemoryOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn messages(&self) -> Vec<(PlayerId, OutgoingMessage)> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    /// Instructions received by one seat, in the order they were sent.
+    pub fn instructions_for(&self, player_id: PlayerId) -> Vec<InstructionToClient> {
+        self.messages.lock().unwrap().iter()
+            .filter(|(recipient, _)| *recipient == player_id)
+            .filter_map(|(_, message)| match message {
+                OutgoingMessage::Instruction(instruction) => Some(instruction.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.messages.lock().unwrap().clear();
+    }
+}
+
+impl OutputSink for MemoryOutput {
+    fn send(&mut self, recipient: PlayerId, message: OutgoingMessage) -> BoxFuture<'_, Result<()>> {
+        self.messages.lock().unwrap().push((recipient, message));
+        Box::pin(async { Ok(()) })
+    }
+}
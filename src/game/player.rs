@@ -1,5 +1,6 @@
 use color_eyre::eyre::{ContextCompat, eyre};
 use color_eyre::Result;
+use serde::{Deserialize, Serialize};
 use crate::game::animation_presets::AnimationPreset;
 use crate::game::board::Board;
 use crate::game::tokens::token_deserializer::TokenCategory;
@@ -10,7 +11,7 @@ use crate::game::instruction::InstructionToClient;
 use crate::game::new_state_machine::StateMachine;
 use crate::game::state_resources::StateResources;
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Player {
     pub thaum: u32,
     pub id: PlayerId,
@@ -51,11 +52,11 @@ impl Player {
         }).await
     }
 
-    pub async fn populate_set(player_id: PlayerId, data: &str, resources: &mut StateResources, communicator: &mut GameCommunicator) -> Result<()> {
+    pub async fn populate_set(player_id: PlayerId, set: &[String], resources: &mut StateResources, communicator: &mut GameCommunicator) -> Result<()> {
         let player = resources.get_player(player_id);
         let player_set = player.set;
-        for split in data.split(',') {
-            resources.create_token(split, player_set, player_id, communicator).await?;
+        for id in set {
+            resources.create_token(id, player_set, player_id, communicator).await?;
         }
 
         Ok(())
@@ -81,6 +82,7 @@ impl Player {
                 resources.get_player_mut(player_id).hero = hero;
                 let hero_location = resources.board.get_side(player_id).hero;
                 resources.move_token(hero, hero_location, None, communicator).await?;
+                resources.reveal_token(hero, communicator).await?;
             }
             0 => return Err(eyre!("No hero found in set")),
             _ => return Err(eyre!("Found more than one hero in set")),
@@ -102,12 +104,13 @@ impl Player {
                 resources.get_player_mut(player_id).landscape = landscape;
                 let landscape_location = resources.board.get_side(player_id).landscape;
                 resources.move_token(landscape, landscape_location, None, communicator).await?;
+                resources.reveal_token(landscape, communicator).await?;
             }
             0 => return Err(eyre!("No hero found in set")),
             _ => return Err(eyre!("Found more than one hero in set")),
         }
 
-        resources.locations.get_mut(&player_set).context("Set was not found")?.shuffle();
+        resources.locations.get_mut(&player_set).context("Set was not found")?.shuffle(&resources.rng);
 
         Ok(())
     }
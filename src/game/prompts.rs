@@ -1,17 +1,19 @@
-use std::collections::HashMap;
-use color_eyre::eyre::ContextCompat;
+use std::collections::BTreeMap;
+use color_eyre::eyre::{ContextCompat, eyre};
 
 use color_eyre::Result;
+use serde::{Deserialize, Serialize};
 
 use crate::game::game_communicator::GameCommunicator;
+use crate::game::game_rng::GameRng;
 use crate::game::id_types::{TokenInstanceId, LocationId, PlayerId, PromptInstanceId};
 use crate::game::instruction::InstructionToClient;
 use crate::game::tag::get_tag;
 use crate::game::game_context::GameContext;
-use crate::game::new_state_machine::StateMachine;
+use crate::game::new_state_machine::{StateMachine, StateTransitionGroup};
 use crate::game::state_resources::StateResources;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PromptType {
     SelectToken(TokenInstanceId),
     AttackToken(TokenInstanceId),
@@ -41,6 +43,7 @@ impl PromptType {
 
 pub type PromptCallbackClosure = fn(callback_data: PromptInstance, context: &mut GameContext, state: &mut StateMachine, resources: &mut StateResources, communicator: &mut GameCommunicator) -> Result<PromptCallbackResult>;
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PromptProfile {
     pub prompt_type: PromptType,
     pub value: bool,
@@ -52,30 +55,62 @@ pub enum PromptCallbackResult {
     End(Option<PromptCallback>)
 }
 
+/// What a callback asks the player for. Callbacks are rebuilt from this when a game is restored from a snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PromptKind {
+    SelectAttacker,
+    SelectAttackTarget,
+    SelectCastTarget,
+}
+
+impl PromptKind {
+    fn closure(&self) -> PromptCallbackClosure {
+        match self {
+            PromptKind::SelectAttacker => StateResources::on_attacker_selected,
+            PromptKind::SelectAttackTarget => StateResources::on_attack_target_selected,
+            PromptKind::SelectCastTarget => StateTransitionGroup::on_cast_target_selected,
+        }
+    }
+}
+
 pub struct PromptCallback {
+    pub kind: PromptKind,
     pub cancelable: bool,
     closure: PromptCallbackClosure,
-    prompt_instances: HashMap<PromptInstanceId, PromptProfile>,
+    /// Ordered so prompts are sent the same way every time a game is replayed.
+    pub prompt_instances: BTreeMap<PromptInstanceId, PromptProfile>,
     pub context: GameContext
 }
 
 impl PromptCallback {
-    pub fn new(closure: PromptCallbackClosure, cancelable: bool) -> Self {
+    pub fn new(kind: PromptKind, cancelable: bool) -> Self {
         Self {
+            kind,
             cancelable,
-            closure,
-            prompt_instances: HashMap::new(),
+            closure: kind.closure(),
+            prompt_instances: BTreeMap::new(),
             context: GameContext::new()
         }
     }
 
-    pub fn add_prompt(&mut self, prompt: PromptProfile) {
-        self.prompt_instances.insert(PromptInstanceId(fastrand::u64(..)), prompt);
+    pub fn add_prompt(&mut self, prompt: PromptProfile, rng: &GameRng) {
+        self.prompt_instances.insert(PromptInstanceId(rng.json_safe_u64()), prompt);
+    }
+
+    /// Sends the prompts of one player again, e.g. after they reconnected.
+    pub async fn create_instructions_for(&self, player_id: PlayerId, communicator: &mut GameCommunicator) -> Result<()> {
+        for (id, prompt) in self.prompt_instances.iter().filter(|(_, prompt)| prompt.owner == player_id) {
+            communicator.send_game_instruction_to(player_id, InstructionToClient::AddPrompt {
+                prompt_instance_id: *id,
+                prompt_type: prompt.prompt_type,
+            }).await?;
+        }
+        Ok(())
     }
 
     pub async fn create_instructions(&self, communicator: &mut GameCommunicator) -> Result<()> {
         for (id, prompt) in &self.prompt_instances {
-            communicator.send_game_instruction(InstructionToClient::AddPrompt {
+            communicator.send_game_instruction_to(prompt.owner, InstructionToClient::AddPrompt {
                 prompt_instance_id: *id,
                 prompt_type: prompt.prompt_type,
             }).await?;
@@ -83,16 +118,22 @@ impl PromptCallback {
         Ok(())
     }
 
-    pub fn execute(&mut self, data: String, context: &mut GameContext, state: &mut StateMachine, resources: &mut StateResources, communicator: &mut GameCommunicator) -> Result<PromptCallbackResult> {
-        let prompt_instance_id = PromptInstanceId(get_tag("callback_id", &data)?.parse::<u64>()?);
-        let value = get_tag("value", &data)?.parse::<bool>()?;
-        let prompt_type = self.prompt_instances.get(&prompt_instance_id).context("Failed to find prompt with given instance id")?.prompt_type;
+    pub fn execute(&mut self, player_id: PlayerId, prompt_instance_id: PromptInstanceId, value: bool, context: &mut GameContext, state: &mut StateMachine, resources: &mut StateResources, communicator: &mut GameCommunicator) -> Result<PromptCallbackResult> {
+        let prompt = self.prompt_instances.get(&prompt_instance_id).context("Failed to find prompt with given instance id")?;
+        if prompt.owner != player_id {
+            return Err(eyre!("This prompt belongs to the other player"));
+        }
+        let prompt_type = prompt.prompt_type;
         (self.closure)(PromptInstance { prompt: prompt_type, value }, context, state, resources, communicator)
     }
 
+    pub fn is_owned_by(&self, player_id: PlayerId) -> bool {
+        self.prompt_instances.values().all(|prompt| prompt.owner == player_id)
+    }
+
     pub async fn cancel(&mut self, communicator: &mut GameCommunicator) -> Result<()> {
         for (id, prompt) in &self.prompt_instances {
-            communicator.send_game_instruction(InstructionToClient::RemovePrompt {
+            communicator.send_game_instruction_to(prompt.owner, InstructionToClient::RemovePrompt {
                 prompt_instance_id: *id,
             }).await?;
         }
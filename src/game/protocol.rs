@@ -0,0 +1,125 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::game::id_types::{LocationId, PromptInstanceId, TokenInstanceId};
+use crate::game::tag::get_tag;
+
+/// Websocket subprotocol a client offers to talk JSON. Later versions of the protocol get a new name.
+pub const JSON_SUBPROTOCOL: &str = "landmark.json.v1";
+
+/// How messages are encoded for one seat, picked once during the websocket handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    /// `instruction|//value/!` messages as understood by existing clients.
+    #[default]
+    Legacy,
+    Json,
+}
+
+impl WireFormat {
+    /// Reads the `Sec-WebSocket-Protocol` header of a handshake. Clients that don't ask for JSON keep the legacy format.
+    pub fn negotiate(offered: Option<&str>) -> Self {
+        let offered = offered.unwrap_or("");
+        match offered.split(',').any(|protocol| protocol.trim() == JSON_SUBPROTOCOL) {
+            true => WireFormat::Json,
+            false => WireFormat::Legacy,
+        }
+    }
+
+    /// The subprotocol the server agrees to, if any.
+    pub fn subprotocol(&self) -> Option<&'static str> {
+        match self {
+            WireFormat::Legacy => None,
+            WireFormat::Json => Some(JSON_SUBPROTOCOL),
+        }
+    }
+}
+
+/// Everything a client can ask the game to do. The schema of both wire formats is documented in `docs/client_commands.md`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum InstructionFromClient {
+    /// Starts a new game, only Player 1 may send it. Decks are lists of token ids, each with one hero and one landscape.
+    StartGame {
+        set_1: Vec<String>,
+        set_2: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seed: Option<u64>,
+    },
+    /// Plays a token from hand: summons a unit to a field slot, equips an item or casts a command.
+    MoveToken {
+        token: TokenInstanceId,
+        location: LocationId,
+    },
+    PassTurn,
+    /// Answers an open prompt.
+    Callback {
+        callback_id: PromptInstanceId,
+        value: bool,
+    },
+    /// Gives up the running game, the opponent wins.
+    Concede,
+    /// Asks to play again with the same sets once the game is over.
+    Rematch,
+}
+
+impl InstructionFromClient {
+    /// Parses and validates a message, the error explains what is wrong so it can go straight back to the client.
+    pub fn parse(format: WireFormat, message: &str) -> Result<Self> {
+        let command = match format {
+            WireFormat::Legacy => Self::from_legacy(message)?,
+            WireFormat::Json => serde_json::from_str(message).map_err(|e| eyre!("Invalid instruction: {}", e))?,
+        };
+        command.validate()?;
+        Ok(command)
+    }
+
+    /// `instruction|data`, where only the first `|` separates the two.
+    pub fn from_legacy(message: &str) -> Result<Self> {
+        let (instruction, data) = message.split_once('|').unwrap_or((message, ""));
+
+        Ok(match instruction {
+            "start_game" => InstructionFromClient::StartGame {
+                set_1: legacy_field::<String>(instruction, "set1", data)?.split(',').map(|id| id.to_string()).collect(),
+                set_2: legacy_field::<String>(instruction, "set2", data)?.split(',').map(|id| id.to_string()).collect(),
+                seed: match get_tag("seed", data) {
+                    Ok(_) => Some(legacy_field(instruction, "seed", data)?),
+                    Err(_) => None,
+                },
+            },
+            "move_token" => InstructionFromClient::MoveToken {
+                token: legacy_field(instruction, "token", data)?,
+                location: legacy_field(instruction, "location", data)?,
+            },
+            "pass_turn" => InstructionFromClient::PassTurn,
+            "callback" => InstructionFromClient::Callback {
+                callback_id: PromptInstanceId(legacy_field(instruction, "callback_id", data)?),
+                value: legacy_field(instruction, "value", data)?,
+            },
+            "concede" => InstructionFromClient::Concede,
+            "rematch" => InstructionFromClient::Rematch,
+            _ => return Err(eyre!("Unknown instruction: {}", instruction)),
+        })
+    }
+
+    /// Checks what the types alone can't express.
+    pub fn validate(&self) -> Result<()> {
+        if let InstructionFromClient::StartGame { set_1, set_2, .. } = self {
+            for (name, set) in [("set_1", set_1), ("set_2", set_2)] {
+                if set.iter().any(|id| id.trim().is_empty()) {
+                    return Err(eyre!("start_game: {} contains an empty token id", name));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn legacy_field<T: FromStr>(instruction: &str, name: &str, data: &str) -> Result<T> where T::Err: Display {
+    let value = get_tag(name, data).map_err(|_| eyre!("{}: missing /{}/", instruction, name))?;
+    value.parse::<T>().map_err(|e| eyre!("{}: invalid {} \"{}\": {}", instruction, name, value, e))
+}
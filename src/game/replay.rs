@@ -0,0 +1,133 @@
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::game::game_communicator::GameCommunicator;
+use crate::game::game_service::GameSession;
+use crate::game::id_types::PlayerId;
+use crate::game::protocol::InstructionFromClient;
+
+/// One line of a replay file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "event")]
+pub enum ReplayEvent {
+    /// The seed a game was started with, recorded while its start_game message is handled.
+    Seed { seed: u64 },
+    /// Commands are recorded as they were understood, whichever wire format the client used.
+    Received { player_id: PlayerId, command: InstructionFromClient },
+    Sent { player_id: PlayerId, message: String },
+}
+
+/// Writes a replay as JSON lines while the game is running, so a crashed server still leaves a usable replay behind.
+pub struct ReplayRecorder {
+    writer: Box<dyn Write + Send>,
+}
+
+impl ReplayRecorder {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self { writer }
+    }
+
+    pub fn create(path: &Path) -> Result<Self> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        println!("Recording replay to {}", path.display());
+        Ok(Self::new(Box::new(File::create(path)?)))
+    }
+
+    /// Where the replay of a session is written, sessions are named by the clients so only a safe part of the name is used.
+    pub fn session_path(session: &str) -> Box<Path> {
+        let session = session.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_').collect::<String>();
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        Path::new("replays").join(format!("{}-{}.jsonl", session, started)).into_boxed_path()
+    }
+
+    pub fn record(&mut self, event: &ReplayEvent) -> Result<()> {
+        writeln!(self.writer, "{}", serde_json::to_string(event)?)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReplayResult {
+    Matches { messages: usize },
+    /// The first sent message that differs from the recording, `None` where one side ran out of messages.
+    Diverged { index: usize, expected: Option<String>, actual: Option<String> },
+}
+
+pub struct Replay {
+    pub events: Vec<ReplayEvent>,
+}
+
+impl Replay {
+    pub fn from_file(path: &str) -> Result<Self> {
+        println!("Loading replay from {}", path);
+        Self::from_str(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_str(data: &str) -> Result<Self> {
+        let mut events = Vec::new();
+        for (index, line) in data.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            events.push(serde_json::from_str(line).context(format!("Invalid replay event on line {}", index + 1))?);
+        }
+        Ok(Self { events })
+    }
+
+    /// Feeds the recorded messages to a fresh game and compares everything it sends with the recording.
+    pub async fn verify(&self) -> Result<ReplayResult> {
+        let (communicator, output) = GameCommunicator::headless();
+        let mut game = GameSession::new(communicator);
+
+        for (index, event) in self.events.iter().enumerate() {
+            let ReplayEvent::Received { player_id, command } = event else { continue };
+            // The game service stops at the first error, and so does the replay
+            if game.handle_command(*player_id, self.seeded(index, command)).await.is_err() {
+                break;
+            }
+        }
+
+        let expected = self.events.iter().filter_map(|event| match event {
+            ReplayEvent::Sent { message, .. } => Some(message.clone()),
+            _ => None,
+        }).collect::<Vec<String>>();
+        let mut actual = Vec::new();
+        for (recipient, message) in output.messages() {
+            actual.push(message.build(recipient).await?);
+        }
+
+        for index in 0..expected.len().max(actual.len()) {
+            if expected.get(index) != actual.get(index) {
+                return Ok(ReplayResult::Diverged {
+                    index,
+                    expected: expected.get(index).cloned(),
+                    actual: actual.get(index).cloned(),
+                });
+            }
+        }
+        Ok(ReplayResult::Matches { messages: actual.len() })
+    }
+
+    /// Games started without a seed picked one at random, which the replay has to reuse.
+    fn seeded(&self, index: usize, command: &InstructionFromClient) -> InstructionFromClient {
+        let InstructionFromClient::StartGame { set_1, set_2, seed: None } = command else {
+            return command.clone();
+        };
+
+        let seed = self.events[index..].iter().find_map(|event| match event {
+            ReplayEvent::Seed { seed } => Some(*seed),
+            _ => None,
+        });
+        InstructionFromClient::StartGame { set_1: set_1.clone(), set_2: set_2.clone(), seed }
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use color_eyre::eyre::{Context, ContextCompat};
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::game::board::Board;
+use crate::game::game_communicator::GameCommunicator;
+use crate::game::game_context::GameContext;
+use crate::game::game_over::GameOver;
+use crate::game::game_rng::GameRng;
+use crate::game::game_service::GameSession;
+use crate::game::id_types::{location_ids, LocationId, PlayerId, PromptInstanceId, ServerInstanceId, TokenInstanceId};
+use crate::game::instruction::InstructionToClient;
+use crate::game::locations::token_collection::TokenCollection;
+use crate::game::locations::token_slot::TokenSlot;
+use crate::game::new_state_machine::{StateMachine, StateTransitionGroup};
+use crate::game::player::Player;
+use crate::game::prompts::{PromptCallback, PromptKind, PromptProfile};
+use crate::game::state_resources::{StateResources, ThreadSafeLocation};
+use crate::game::tokens::token_deserializer::{TokenBehavior, TokenBehaviorTriggerWhenName as TriggerState};
+use crate::game::tokens::token_instance::{TokenInstance, UnitStats};
+use crate::TOKEN_REGISTRY;
+
+/// Everything needed to pick a game back up where it was left, e.g. after the server restarted.
+/// Taken between messages, so the state machine only holds groups that wait for a prompt.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GameSnapshot {
+    pub seed: u64,
+    pub rng_state: u64,
+    pub round: u32,
+    pub current_turn: PlayerId,
+    pub player_1: Player,
+    pub player_2: Player,
+    pub board: Board,
+    pub locations: Vec<LocationSnapshot>,
+    pub tokens: Vec<TokenSnapshot>,
+    pub equipment_slot_owners: Vec<(LocationId, TokenInstanceId)>,
+    pub player_1_equipment_slot_counter: ServerInstanceId,
+    pub player_2_equipment_slot_counter: ServerInstanceId,
+    pub transition_groups: Vec<TransitionGroupSnapshot>,
+    pub callback: Option<CallbackSnapshot>,
+    pub callback_context: GameContext,
+    #[serde(default)]
+    pub batches_sent: u64,
+    #[serde(default)]
+    pub sets: (Vec<String>, Vec<String>),
+    #[serde(default)]
+    pub game_over: Option<GameOver>,
+    /// Rejoin tokens of the game, only connections that present them get it back.
+    #[serde(default)]
+    pub seats: Vec<(String, PlayerId)>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LocationSnapshot {
+    pub location_id: LocationId,
+    pub tokens: Vec<TokenInstanceId>,
+}
+
+/// A token instance that refers to its token data by id instead of by reference.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TokenSnapshot {
+    pub token: String,
+    pub owner: PlayerId,
+    pub location: LocationId,
+    pub instance_id: TokenInstanceId,
+    pub behaviors: Vec<TokenBehavior>,
+    pub cost: u32,
+    pub base_stats: UnitStats,
+    pub current_stats: UnitStats,
+    pub equipment_slots: Vec<LocationId>,
+    pub token_types: Vec<String>,
+    pub hidden: bool,
+    pub counters: BTreeMap<String, i32>,
+    pub personal_context: GameContext,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TransitionGroupSnapshot {
+    pub states: Vec<TriggerState>,
+    pub context: GameContext,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CallbackSnapshot {
+    pub kind: PromptKind,
+    pub cancelable: bool,
+    pub prompts: Vec<(PromptInstanceId, PromptProfile)>,
+    pub context: GameContext,
+}
+
+impl GameSnapshot {
+    /// Where the snapshot of a session is kept. Unlike replays there is only one per session, so a restarted server can find it again.
+    /// Snapshots are filed under the id the server gave the game, never under a name a client picked.
+    pub fn path(game_id: &str) -> Box<Path> {
+        Path::new("snapshots").join(format!("{}.json", game_id)).into_boxed_path()
+    }
+
+    /// The saved game both connections hold a seat in. Connections without its rejoin tokens never get it.
+    pub fn find(rejoin_tokens: [Option<&str>; 2]) -> Option<Self> {
+        let [Some(first), Some(second)] = rejoin_tokens else { return None };
+        let game_id = game_id_of(first)?;
+        let path = Self::path(game_id);
+        if game_id_of(second)? != game_id || path.exists() == false {
+            return None;
+        }
+
+        let snapshot = Self::from_file(&path).map_err(|e| eprintln!("Unable to resume game: {}", e)).ok()?;
+        match (snapshot.seat_of(first), snapshot.seat_of(second)) {
+            (Some(seat_1), Some(seat_2)) if seat_1 != seat_2 => Some(snapshot),
+            _ => None,
+        }
+    }
+
+    pub fn seat_of(&self, rejoin_token: &str) -> Option<PlayerId> {
+        self.seats.iter().find(|(token, _)| token == rejoin_token).map(|(_, seat)| *seat)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        println!("Loading snapshot from {}", path.display());
+        serde_json::from_str(&fs::read_to_string(path)?).context(format!("Invalid snapshot {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Rejoin tokens are `<game id>.<secret>`, the game id names the snapshot file.
+pub fn new_rejoin_token(game_id: &str) -> String {
+    format!("{}.{:016x}", game_id, fastrand::u64(..))
+}
+
+pub fn game_id_of(rejoin_token: &str) -> Option<&str> {
+    let (game_id, _) = rejoin_token.split_once('.')?;
+    match game_id.is_empty() == false && game_id.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => Some(game_id),
+        false => None,
+    }
+}
+
+impl TokenSnapshot {
+    fn from_instance(token: &TokenInstance) -> Self {
+        Self {
+            token: token.token_data.id.clone(),
+            owner: token.owner,
+            location: token.location,
+            instance_id: token.instance_id,
+            behaviors: token.behaviors.clone(),
+            cost: token.cost,
+            base_stats: token.base_stats,
+            current_stats: token.current_stats,
+            equipment_slots: token.equipment_slots.clone(),
+            token_types: token.token_types.clone(),
+            hidden: token.hidden,
+            counters: token.counters.iter().map(|(id, value)| (id.clone(), *value)).collect(),
+            personal_context: token.personal_context.clone(),
+        }
+    }
+
+    async fn into_instance(self) -> Result<TokenInstance> {
+        let token_data = *TOKEN_REGISTRY.lock().await.token_registry.get(&self.token).context(format!("Snapshot refers to an unknown token: {}", self.token))?;
+        Ok(TokenInstance {
+            token_data,
+            owner: self.owner,
+            location: self.location,
+            instance_id: self.instance_id,
+            behaviors: self.behaviors,
+            cost: self.cost,
+            base_stats: self.base_stats,
+            current_stats: self.current_stats,
+            equipment_slots: self.equipment_slots,
+            token_types: self.token_types,
+            hidden: self.hidden,
+            counters: self.counters.into_iter().collect(),
+            personal_context: self.personal_context,
+        })
+    }
+}
+
+impl GameSession {
+    pub fn snapshot(&self) -> GameSnapshot {
+        let resources = &self.resources;
+
+        let mut locations = resources.locations.values().map(|location| LocationSnapshot {
+            location_id: location.get_location_id(),
+            tokens: location.get_tokens(),
+        }).collect::<Vec<LocationSnapshot>>();
+        locations.sort_by_key(|location| location.location_id.0);
+
+        let mut equipment_slot_owners = resources.equipment_slot_owners.iter().map(|(slot, unit)| (*slot, *unit)).collect::<Vec<_>>();
+        equipment_slot_owners.sort_by_key(|(slot, _)| slot.0);
+
+        GameSnapshot {
+            seed: resources.rng.seed,
+            rng_state: resources.rng.state(),
+            round: resources.round,
+            current_turn: resources.current_turn,
+            player_1: resources.player_1.clone(),
+            player_2: resources.player_2.clone(),
+            board: resources.board.clone(),
+            locations,
+            tokens: resources.get_tokens().iter().map(|token| TokenSnapshot::from_instance(token)).collect(),
+            equipment_slot_owners,
+            player_1_equipment_slot_counter: resources.player_1_equipment_slot_counter,
+            player_2_equipment_slot_counter: resources.player_2_equipment_slot_counter,
+            transition_groups: self.state.state_transition_groups.iter().map(|group| TransitionGroupSnapshot {
+                states: group.states.iter().cloned().collect(),
+                context: group.context.clone(),
+            }).collect(),
+            callback: self.current_callback.as_ref().map(|callback| CallbackSnapshot {
+                kind: callback.kind,
+                cancelable: callback.cancelable,
+                prompts: callback.prompt_instances.iter().map(|(id, prompt)| (*id, prompt.clone())).collect(),
+                context: callback.context.clone(),
+            }),
+            callback_context: self.callback_context.clone(),
+            batches_sent: self.communicator.batches_sent(),
+            sets: resources.sets.clone(),
+            game_over: resources.game_over.clone(),
+            seats: Vec::new(),
+        }
+    }
+
+    pub async fn restore(snapshot: GameSnapshot, mut communicator: GameCommunicator) -> Result<GameSession> {
+        communicator.set_batches_sent(snapshot.batches_sent);
+        let mut resources = StateResources::new();
+        resources.rng = GameRng::restore(snapshot.seed, snapshot.rng_state);
+        resources.round = snapshot.round;
+        resources.current_turn = snapshot.current_turn;
+        resources.player_1 = snapshot.player_1;
+        resources.player_2 = snapshot.player_2;
+        resources.board = snapshot.board;
+        resources.equipment_slot_owners = snapshot.equipment_slot_owners.into_iter().collect::<HashMap<_, _>>();
+        resources.player_1_equipment_slot_counter = snapshot.player_1_equipment_slot_counter;
+        resources.player_2_equipment_slot_counter = snapshot.player_2_equipment_slot_counter;
+        resources.sets = snapshot.sets;
+        resources.game_over = snapshot.game_over;
+
+        for location in snapshot.locations {
+            let mut restored: Box<ThreadSafeLocation> = match location_ids::identify_location(location.location_id)? {
+                location_ids::LocationIdentity::Player1Set | location_ids::LocationIdentity::Player2Set
+                | location_ids::LocationIdentity::Player1Hand | location_ids::LocationIdentity::Player2Hand
+                | location_ids::LocationIdentity::Player1Graveyard | location_ids::LocationIdentity::Player2Graveyard
+                => Box::new(TokenCollection::new(location.location_id)),
+                _ => Box::new(TokenSlot::new(location.location_id)),
+            };
+            for token in location.tokens {
+                restored.add_token(token)?;
+            }
+            resources.locations.insert(location.location_id, restored);
+        }
+
+        for token in snapshot.tokens {
+            let token = token.into_instance().await?;
+            resources.token_instances.insert(token.instance_id, token);
+        }
+
+        let mut state = StateMachine::new();
+        for group in snapshot.transition_groups {
+            state.state_transition_groups.push_back(StateTransitionGroup {
+                states: group.states.into_iter().collect::<VecDeque<TriggerState>>(),
+                context: group.context,
+            });
+        }
+
+        let current_callback = snapshot.callback.map(|snapshot| {
+            let mut callback = PromptCallback::new(snapshot.kind, snapshot.cancelable);
+            callback.prompt_instances = snapshot.prompts.into_iter().collect();
+            callback.context = snapshot.context;
+            callback
+        });
+
+        Ok(GameSession {
+            communicator,
+            state,
+            resources,
+            current_callback,
+            callback_context: snapshot.callback_context,
+        })
+    }
+
+    /// Sends the whole board to a single player, e.g. one that reconnected or joined a restored game.
+    /// The client drops whatever it showed before once it gets the resync.
+    pub async fn resend_state(&mut self, player_id: PlayerId) -> Result<()> {
+        let Self { communicator, resources, current_callback, .. } = self;
+
+        communicator.send_game_instruction_to(player_id, InstructionToClient::Resync).await?;
+
+        let types = TOKEN_REGISTRY.lock().await.type_registry.get_type_names();
+        communicator.send_game_instruction_to(player_id, InstructionToClient::TypeNames { types }).await?;
+
+        for side in [&resources.board.side_1, &resources.board.side_2] {
+            for (index, location_id) in side.field.iter().enumerate() {
+                communicator.send_game_instruction_to(player_id, InstructionToClient::AddLandscapeSlot {
+                    player_id: side.owner,
+                    index: index as u64,
+                    location_id: *location_id,
+                }).await?;
+            }
+        }
+
+        // Items go last, the slots they sit in only exist once their unit has been created
+        let mut locations = resources.locations.keys().copied().collect::<Vec<LocationId>>();
+        locations.sort_by_key(|location| (location_ids::identify_location(*location).map_or(false, |identity| identity.is_item_slot()), location.0));
+        for location in locations {
+            let tokens = resources.locations.get(&location).context("Location disappeared while resending state")?.get_tokens();
+            for token_instance_id in tokens {
+                let token = resources.token_instances.get(&token_instance_id).context("Location refers to a token that does not exist")?;
+                resend_token(player_id, token, communicator).await?;
+            }
+        }
+
+        for player in [PlayerId::Player1, PlayerId::Player2] {
+            communicator.send_game_instruction_to(player_id, InstructionToClient::SetThaum {
+                player_id: player,
+                amount: resources.get_player(player).thaum,
+            }).await?;
+        }
+        communicator.send_game_instruction_to(player_id, InstructionToClient::PassTurn { player_id: resources.current_turn }).await?;
+
+        if let Some(callback) = current_callback {
+            callback.create_instructions_for(player_id, communicator).await?;
+        }
+        if let Some(game_over) = &resources.game_over {
+            communicator.send_game_instruction_to(player_id, InstructionToClient::EndGame {
+                winner: game_over.winner,
+                reason: game_over.reason,
+                summary: game_over.summary.clone(),
+            }).await?;
+            for requested_by in &game_over.rematch_requests {
+                communicator.send_game_instruction_to(player_id, InstructionToClient::RematchRequested { player_id: *requested_by }).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn resend_token(player_id: PlayerId, token: &TokenInstance, communicator: &mut GameCommunicator) -> Result<()> {
+    communicator.send_game_instruction_to(player_id, InstructionToClient::CreateToken {
+        token_data: token.clone(),
+        instance_id: token.instance_id,
+        location_id: token.location,
+        player_id: token.owner,
+    }).await?;
+    communicator.send_game_instruction_to(player_id, InstructionToClient::UpdateBehaviors { token_data: token.clone() }).await?;
+    communicator.send_game_instruction_to(player_id, InstructionToClient::UpdateCounters { token_data: token.clone() }).await?;
+    for slot in &token.equipment_slots {
+        communicator.send_game_instruction_to(player_id, InstructionToClient::AddEquipmentSlot { token: token.instance_id, slot_location_id: *slot }).await?;
+    }
+    Ok(())
+}
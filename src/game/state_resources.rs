@@ -3,14 +3,18 @@ use std::collections::{HashMap, VecDeque};
 use color_eyre::eyre::{Context, ContextCompat, eyre};
 use color_eyre::Result;
 use futures_util::FutureExt;
+use async_recursion::async_recursion;
 use crate::TOKEN_REGISTRY;
 use crate::game::animation_presets::AnimationPreset;
 
 use crate::game::board::Board;
 use crate::game::tokens;
+use crate::game::tokens::counter_registry::counter_ids;
 use crate::game::tokens::token_deserializer::{TokenBehavior, TokenBehaviorAction, TokenBehaviorTriggerWhenName, TokenCategory};
 use crate::game::tokens::token_instance::TokenInstance;
 use crate::game::game_communicator::GameCommunicator;
+use crate::game::game_over::{GameOver, GameOverReason, GameSummary};
+use crate::game::game_rng::GameRng;
 use crate::game::id_types::{TokenInstanceId, location_ids, LocationId, PlayerId, ServerInstanceId};
 use crate::game::instruction::InstructionToClient;
 use crate::game::new_state_machine::{StateMachine, StateTransitionGroup};
@@ -18,7 +22,7 @@ use crate::game::player::Player;
 use crate::game::game_context::{context_keys, ContextValue, GameContext};
 use crate::game::locations::location::Location;
 use crate::game::locations::token_slot::TokenSlot;
-use crate::game::prompts::{PromptCallback, PromptInstance, PromptCallbackResult, PromptProfile, PromptType};
+use crate::game::prompts::{PromptCallback, PromptInstance, PromptCallbackResult, PromptKind, PromptProfile, PromptType};
 use crate::game::tag::get_tag;
 
 pub type ThreadSafeLocation = dyn Location + Send + Sync;
@@ -32,12 +36,17 @@ pub struct StateResources {
     pub player_2: Player,
     pub current_turn: PlayerId,
     pub board: Board,
-    player_1_equipment_slot_counter: ServerInstanceId,
-    player_2_equipment_slot_counter: ServerInstanceId,
+    pub rng: GameRng,
+    pub player_1_equipment_slot_counter: ServerInstanceId,
+    pub player_2_equipment_slot_counter: ServerInstanceId,
+    /// The sets the current game was started with, a rematch is played with them again.
+    pub sets: (Vec<String>, Vec<String>),
+    pub game_over: Option<GameOver>,
 }
 
 impl StateResources {
     pub fn new() -> Self {
+        let rng = GameRng::from_entropy();
         Self {
             locations: HashMap::new(),
             token_instances: HashMap::new(),
@@ -45,17 +54,35 @@ impl StateResources {
             round: 0,
             player_1: Player::new(PlayerId::Player1, location_ids::PLAYER_1_SET, location_ids::PLAYER_1_HAND),
             player_2: Player::new(PlayerId::Player2, location_ids::PLAYER_2_SET, location_ids::PLAYER_2_HAND),
-            current_turn: if fastrand::bool() { PlayerId::Player1 } else { PlayerId::Player2 },
+            current_turn: if rng.bool() { PlayerId::Player1 } else { PlayerId::Player2 },
             board: Board::new(),
+            rng,
             player_1_equipment_slot_counter: 10000,
             player_2_equipment_slot_counter: 20000,
+            sets: (Vec::new(), Vec::new()),
+            game_over: None,
         }
     }
 
+    /// Restarts the randomness of the game from a seed, including who gets the first turn.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = GameRng::new(seed);
+        self.current_turn = if self.rng.bool() { PlayerId::Player1 } else { PlayerId::Player2 };
+    }
+
     pub async fn reset_game(&mut self, communicator: &mut GameCommunicator) -> Result<()> {
-        for key in self.locations.iter().map(|(key, _)| key.clone()).collect::<Vec<_>>() {
+        let mut keys = self.locations.keys().copied().collect::<Vec<LocationId>>();
+        keys.sort_by_key(|key| key.0);
+        for key in keys {
             self.clear_location(key, communicator).await?;
         }
+
+        // Nothing of the last game may carry over, its units took their equipment slots with them
+        for slot in self.equipment_slot_owners.keys() {
+            self.locations.remove(slot);
+        }
+        self.equipment_slot_owners.clear();
+        self.token_instances.clear();
         Ok(())
     }
 
@@ -96,21 +123,32 @@ impl StateResources {
     }
 
     pub async fn create_token(&mut self, id: &str, location: LocationId, owner: PlayerId, communicator: &mut GameCommunicator) -> Result<TokenInstanceId> {
-        let token_instance_id = TokenInstanceId(fastrand::u64(..));
+        let token_instance_id = TokenInstanceId(self.rng.json_safe_u64());
 
-        let loc = self.locations
-            .get_mut(&location)
+        self.locations
+            .get(&location)
             .context("Tried to create a token to a location that does not exist")?;
 
-        let mut token = match (*TOKEN_REGISTRY.lock().await).instance_token(&id, token_instance_id, location, owner) {
+        let token = match (*TOKEN_REGISTRY.lock().await).instance_token(&id, token_instance_id, location, owner) {
             Ok(token) => token,
             Err(e) => {
                 eprintln!("{e}");
                 return Err(e);
             }
         };
-        token.instance_id = token_instance_id;
+        self.add_token(token, location, communicator).await
+    }
+
+    /// Puts a freshly instanced token into a location and tells the clients about it.
+    pub async fn add_token(&mut self, mut token: TokenInstance, location: LocationId, communicator: &mut GameCommunicator) -> Result<TokenInstanceId> {
+        let token_instance_id = token.instance_id;
+        let owner = token.owner;
+        let loc = self.locations
+            .get_mut(&location)
+            .context("Tried to create a token to a location that does not exist")?;
+
         token.location = location;
+        token.hidden = location_ids::identify_location(location)?.is_private();
 
         communicator.send_game_instruction(InstructionToClient::CreateToken {
             token_data: token.clone(),
@@ -144,15 +182,63 @@ impl StateResources {
         Ok(())
     }
 
+    /// Decides the game. The state machine drops whatever is still queued and only a new game or a rematch can follow.
+    pub async fn end_game(&mut self, winner: PlayerId, reason: GameOverReason, communicator: &mut GameCommunicator) -> Result<()> {
+        // Both heroes can fall to the same effect, the first one decides
+        if self.game_over.is_some() {
+            return Ok(())
+        }
+
+        println!("{} won the game ({})", winner, reason);
+        let summary = GameSummary::of(self);
+        communicator.send_game_instruction(InstructionToClient::EndGame { winner, reason, summary: summary.clone() }).await?;
+        self.game_over = Some(GameOver { winner, reason, summary, rematch_requests: Vec::new() });
+        Ok(())
+    }
+
+    /// Notes that a player wants a rematch, true once both of them do.
+    pub async fn request_rematch(&mut self, player_id: PlayerId, communicator: &mut GameCommunicator) -> Result<bool> {
+        let game_over = self.game_over.as_mut().context("The game is not over")?;
+        if game_over.rematch_requests.contains(&player_id) == false {
+            game_over.rematch_requests.push(player_id);
+            communicator.send_game_instruction(InstructionToClient::RematchRequested { player_id }).await?;
+        }
+        Ok(game_over.rematch_requests.len() == 2)
+    }
+
     pub async fn destroy_token(&mut self, token_instance_id: TokenInstanceId, communicator: &mut GameCommunicator) -> Result<()> {
         let token_instance = self.token_instances.get(&token_instance_id).unwrap();
         if matches!(token_instance.token_data.token_category, TokenCategory::Hero { .. }) {
-            communicator.send_game_instruction(InstructionToClient::EndGame { winner: token_instance.owner.opponent() }).await?;
-            return Err(eyre!("Game has concluded"))
+            return self.end_game(token_instance.owner.opponent(), GameOverReason::HeroDefeated, communicator).await
         }
 
         let graveyard = self.board.get_side(token_instance.owner).graveyard;
         self.move_token(token_instance_id, graveyard, Some(AnimationPreset::EaseInOut), communicator).await?;
+        self.reveal_token(token_instance_id, communicator).await?;
+
+        Ok(())
+    }
+
+    /// Makes a token visible to both players and sends its data to the player that could not see it yet.
+    /// Items equipped to the token are revealed along with it.
+    #[async_recursion]
+    pub async fn reveal_token(&mut self, token_instance_id: TokenInstanceId, communicator: &mut GameCommunicator) -> Result<()> {
+        let token_instance = self.token_instances.get_mut(&token_instance_id).context("Tried to reveal a token that does not exist")?;
+        if token_instance.hidden == false {
+            return Ok(())
+        }
+        token_instance.hidden = false;
+        let token_instance = token_instance.clone();
+
+        communicator.send_game_instruction(InstructionToClient::Reveal { token: token_instance_id }).await?;
+        communicator.send_game_instruction(InstructionToClient::UpdateData { token_data: token_instance.clone() }).await?;
+        communicator.send_game_instruction(InstructionToClient::UpdateBehaviors { token_data: token_instance.clone() }).await?;
+
+        for slot in token_instance.equipment_slots {
+            if let Some(item) = self.locations.get(&slot).context("Unable to find equipment slot")?.get_token() {
+                self.reveal_token(item, communicator).await?;
+            }
+        }
 
         Ok(())
     }
@@ -171,48 +257,123 @@ impl StateResources {
         }
     }
 
+    /// All tokens, ordered by instance id so anything that goes through them stays reproducible.
+    pub fn get_tokens(&self) -> Vec<&TokenInstance> {
+        let mut tokens = self.token_instances.values().collect::<Vec<&TokenInstance>>();
+        tokens.sort_by_key(|token| token.instance_id.0);
+        tokens
+    }
+
+    /// Tokens on either field, ordered by instance id so random picks stay reproducible.
+    pub fn get_tokens_on_field(&self) -> Vec<&TokenInstance> {
+        let mut tokens = self.token_instances.values()
+            .filter(|token| location_ids::identify_location(token.location).map_or(false, |location| location.is_field()))
+            .collect::<Vec<&TokenInstance>>();
+        tokens.sort_by_key(|token| token.instance_id.0);
+        tokens
+    }
+
+    /// Finds an empty field slot of a player, preferring slots adjacent to the given token.
+    pub fn find_free_field_slot(&self, player_id: PlayerId, near: Option<TokenInstanceId>, reserved: &[LocationId]) -> Option<LocationId> {
+        let side = self.board.get_side(player_id);
+        let near_position = near
+            .and_then(|token| self.token_instances.get(&token))
+            .and_then(|token| location_ids::get_slot_position(token.location, &self.board).ok());
+
+        let free_slots = side.field.iter().zip(side.field_slot_positions.iter())
+            .filter(|(slot, _)| reserved.contains(slot) == false)
+            .filter(|(slot, _)| self.locations.get(slot).map_or(false, |location| location.has_room()))
+            .collect::<Vec<_>>();
+
+        free_slots.iter()
+            .find(|(_, position)| near_position.map_or(false, |near_position| near_position.is_adjacent_to(**position)))
+            .or(free_slots.first())
+            .map(|(slot, _)| **slot)
+    }
+
+    /// Units can attack once their cooldown has run out, as long as they have attacks left this turn.
+    pub fn can_attack(&self, token: &TokenInstance) -> bool {
+        token.counters.get(counter_ids::ATTACK_COOLDOWN).copied().unwrap_or(0) <= 0
+            && token.counters.get(counter_ids::ATTACKS_LEFT).copied().unwrap_or(0) > 0
+    }
+
+    /// Gives a unit its attacks for the turn and counts down its cooldown.
+    pub async fn ready_unit(&mut self, unit: TokenInstanceId, communicator: &mut GameCommunicator) -> Result<()> {
+        let unit = self.token_instances.get_mut(&unit).context("Unable to find unit to ready")?;
+        unit.counters.insert(counter_ids::ATTACKS_LEFT.to_string(), unit.token_data.token_category.attacks_per_turn());
+        if let Some(cooldown) = unit.counters.get_mut(counter_ids::ATTACK_COOLDOWN) {
+            *cooldown = (*cooldown - 1).max(0);
+        }
+        communicator.send_game_instruction(InstructionToClient::UpdateCounters { token_data: unit.clone() }).await?;
+        Ok(())
+    }
+
+    /// Freshly summoned units have to wait for their owner's next turn before they can attack.
+    pub async fn apply_summoning_sickness(&mut self, unit: TokenInstanceId, communicator: &mut GameCommunicator) -> Result<()> {
+        let unit = self.token_instances.get_mut(&unit).context("Unable to find summoned unit")?;
+        unit.counters.insert(counter_ids::ATTACK_COOLDOWN.to_string(), 1);
+        unit.counters.insert(counter_ids::ATTACKS_LEFT.to_string(), unit.token_data.token_category.attacks_per_turn());
+        communicator.send_game_instruction(InstructionToClient::UpdateCounters { token_data: unit.clone() }).await?;
+        Ok(())
+    }
+
+    pub async fn spend_attack(&mut self, unit: TokenInstanceId, communicator: &mut GameCommunicator) -> Result<()> {
+        let unit = self.token_instances.get_mut(&unit).context("Unable to find attacking unit")?;
+        *unit.counters.entry(counter_ids::ATTACKS_LEFT.to_string()).or_insert(0) -= 1;
+        communicator.send_game_instruction(InstructionToClient::UpdateCounters { token_data: unit.clone() }).await?;
+        Ok(())
+    }
+
+    pub fn on_attacker_selected(prompt: PromptInstance, context: &mut GameContext, state: &mut StateMachine, resources: &mut StateResources, communicator: &mut GameCommunicator) -> Result<PromptCallbackResult> {
+        let new_callback = match prompt.prompt {
+            PromptType::SelectToken(token_instance_id) => {
+                context.insert(context_keys::SELECTED_TOKEN, ContextValue::TokenInstanceId(token_instance_id));
+                Some(resources.show_attackable_tokens(communicator).now_or_never().context("Failed to run async function")??)
+            }
+            _ => None
+        };
+        Ok(PromptCallbackResult::End(new_callback))
+    }
+
+    pub fn on_attack_target_selected(prompt: PromptInstance, context: &mut GameContext, state: &mut StateMachine, resources: &mut StateResources, communicator: &mut GameCommunicator) -> Result<PromptCallbackResult> {
+        match prompt.prompt {
+            PromptType::AttackToken(token_instance_id) => {
+                let attacker = context.get(context_keys::SELECTED_TOKEN)?.as_token_instance_id()?;
+                state.attack(attacker, token_instance_id, false);
+            }
+            _ => {}
+        }
+        Ok(PromptCallbackResult::End(None))
+    }
+
     pub async fn show_selectable_tokens(&self, communicator: &mut GameCommunicator) -> Result<PromptCallback> {
-        let mut callback = PromptCallback::new(|prompt, context, state, resources, communicator| {
-            let new_callback = match prompt.prompt {
-                PromptType::SelectToken(token_instance_id) => {
-                    context.insert(context_keys::SELECTED_TOKEN, ContextValue::TokenInstanceId(token_instance_id));
-                    Some(resources.show_attackable_tokens(communicator).now_or_never().context("Failed to run async function")??)
-                }
-                _ => None
-            };
-            Ok(PromptCallbackResult::End(new_callback))
-        }, true);
-        for (id, token) in &self.token_instances {
+        let mut callback = PromptCallback::new(PromptKind::SelectAttacker, true);
+        for token in self.get_tokens() {
             if token.owner != self.current_turn || location_ids::identify_location(token.location)?.is_field() == false {
                 continue;
             }
 
+            if self.can_attack(token) == false {
+                continue;
+            }
+
             callback.add_prompt(PromptProfile {
-                prompt_type: PromptType::SelectToken(*id),
+                prompt_type: PromptType::SelectToken(token.instance_id),
                 value: false,
                 owner: self.current_turn,
-            })
+            }, &self.rng)
         }
         Ok(callback)
     }
 
     pub async fn show_attackable_tokens(&mut self, communicator: &mut GameCommunicator) -> Result<PromptCallback> {
-        let mut callback = PromptCallback::new(|prompt, context, state, resources, communicator| {
-            match prompt.prompt {
-                PromptType::AttackToken(token_instance_id) => {
-                    let attacker = context.get(context_keys::SELECTED_TOKEN)?.as_token_instance_id()?;
-                    state.attack(attacker, token_instance_id, false);
-                }
-                _ => {}
-            }
-            Ok(PromptCallbackResult::End(None))
-        }, true);
+        let mut callback = PromptCallback::new(PromptKind::SelectAttackTarget, true);
 
         if self.round == 0 {
             return Ok(callback)
         }
 
-        let mut tokens: Vec<&TokenInstance> = self.token_instances.values().collect();
+        let mut tokens = self.get_tokens();
         tokens.retain(|c| location_ids::identify_location(c.location).unwrap().is_field_of(self.current_turn.opponent()));
 
         let front_most_row = tokens.iter().fold(100, |current_min, token| {
@@ -235,7 +396,7 @@ impl StateResources {
                 prompt_type: PromptType::AttackToken(token.instance_id),
                 value: false,
                 owner: self.current_turn,
-            })
+            }, &self.rng)
         }
         Ok(callback)
     }
@@ -248,63 +409,94 @@ impl StateResources {
             return Ok(false);
         }
 
-        let mut allow = true;
+        let mut refusals = Vec::new();
         if token_instance.owner != self.current_turn {
-            communicator.send_error("Can't play token out of turn").await?;
-            allow = false;
+            refusals.push("Can't play token out of turn");
         }
 
         if token_instance.location != self.get_player(token_instance.owner).hand {
-            communicator.send_error("Can't play token from this location").await?;
-            allow = false;
+            refusals.push("Can't play token from this location");
         }
 
         if location_ids::identify_location(to_location)?.is_field() == false {
-            communicator.send_error("Can't summon unit token to this location").await?;
-            allow = false;
+            refusals.push("Can't summon unit token to this location");
         }
 
         if token_instance.cost > self.get_player(self.current_turn).thaum {
-            communicator.send_error("Insufficient Thaum").await?;
-            allow = false;
+            refusals.push("Insufficient Thaum");
         }
 
-        if allow == false {
-            communicator.send_game_instruction(InstructionToClient::MoveToken { token: token_instance_id, to: token_location }).await?;
+        if refusals.is_empty() == false {
+            communicator.refuse(&refusals.join(". "), Some((token_instance_id, token_location))).await?;
         }
 
-        return Ok(allow)
+        return Ok(refusals.is_empty())
+    }
+
+    pub async fn can_player_cast_command(&self, token_instance_id: TokenInstanceId, to_location: LocationId, communicator: &mut GameCommunicator) -> Result<bool> {
+        let token_instance = self.token_instances.get(&token_instance_id).context("Unable to find token")?;
+        let token_location = token_instance.location.clone();
+
+        if token_instance.location == to_location {
+            return Ok(false);
+        }
+
+        let mut refusals = Vec::new();
+        if token_instance.owner != self.current_turn {
+            refusals.push("Can't play token out of turn");
+        }
+
+        if token_instance.location != self.get_player(token_instance.owner).hand {
+            refusals.push("Can't play token from this location");
+        }
+
+        if token_instance.cost > self.get_player(self.current_turn).thaum {
+            refusals.push("Insufficient Thaum");
+        }
+
+        if let TokenCategory::Command { cast_target: Some(filter) } = &token_instance.token_data.token_category {
+            let mut context = GameContext::new();
+            context.insert(context_keys::OWNER, ContextValue::PlayerId(token_instance.owner));
+            context.insert(context_keys::ACTION_THIS, ContextValue::TokenInstanceId(token_instance_id));
+            let mut targets = self.get_tokens_on_field();
+            filter.evaluate(&mut targets, &context, self)?;
+            if targets.is_empty() {
+                refusals.push("Command has no valid targets");
+            }
+        }
+
+        if refusals.is_empty() == false {
+            communicator.refuse(&refusals.join(". "), Some((token_instance_id, token_location))).await?;
+        }
+
+        return Ok(refusals.is_empty())
     }
 
     pub async fn can_player_equip_item(&self, token_instance_id: TokenInstanceId, to_location: LocationId, communicator: &mut GameCommunicator) -> Result<bool> {
         let token_instance = self.token_instances.get(&token_instance_id).context("Unable to find token")?;
         let token_location = token_instance.location.clone();
 
-        let unit_id = self.equipment_slot_owners.get(&to_location).context("Unable to find equipment slot owner");
-        if unit_id.is_err() {
+        let Some(unit_id) = self.equipment_slot_owners.get(&to_location) else {
+            communicator.refuse("Can't equip item token to this location", Some((token_instance_id, token_location))).await?;
             return Ok(false)
-        }
-        let unit_id = unit_id.unwrap();
+        };
         let unit_instance = self.token_instances.get(unit_id).context("Unable to find unit instance to equip to")?;
 
         if token_instance.location == to_location {
             return Ok(false);
         }
 
-        let mut allow = true;
+        let mut refusals = Vec::new();
         if token_instance.owner != self.current_turn {
-            communicator.send_error("Can't play token out of turn").await?;
-            allow = false;
+            refusals.push("Can't play token out of turn");
         }
 
         if token_instance.location != self.get_player(token_instance.owner).hand {
-            communicator.send_error("Can't play token from this location").await?;
-            allow = false;
+            refusals.push("Can't play token from this location");
         }
 
         if location_ids::identify_location(to_location)?.is_item_slot() == false {
-            communicator.send_error("Can't equip item token to this location").await?;
-            allow = false;
+            refusals.push("Can't equip item token to this location");
         }
 
         let mut has_room = false;
@@ -315,20 +507,18 @@ impl StateResources {
             }
         }
         if has_room == false {
-            communicator.send_error("Unit has no more equipment slots").await?;
-            allow = false;
+            refusals.push("Unit has no more equipment slots");
         }
 
         if token_instance.cost > self.get_player(self.current_turn).thaum {
-            communicator.send_error("Insufficient Thaum").await?;
-            allow = false;
+            refusals.push("Insufficient Thaum");
         }
 
-        if allow == false {
-            communicator.send_game_instruction(InstructionToClient::MoveToken { token: token_instance_id, to: token_location }).await?;
+        if refusals.is_empty() == false {
+            communicator.refuse(&refusals.join(". "), Some((token_instance_id, token_location))).await?;
         }
 
-        return Ok(allow)
+        return Ok(refusals.is_empty())
     }
 
     pub async fn set_current_turn(&mut self, player_id: PlayerId, state: &mut StateMachine, communicator: &mut GameCommunicator) -> Result<()> {
@@ -348,6 +538,7 @@ impl StateResources {
         let mut tokens = self.token_instances.values_mut().collect::<Vec<&mut TokenInstance>>();
         tokens.retain(|c| location_ids::identify_location(c.location).unwrap().is_field());
         tokens.retain(|c| c.owner == self.current_turn);
+        let units = tokens.iter().map(|unit| unit.instance_id).collect::<Vec<TokenInstanceId>>();
         for unit in tokens {
             unit.current_stats.defense = unit.base_stats.defense;
             communicator.send_game_instruction(InstructionToClient::Animate {
@@ -365,6 +556,11 @@ impl StateResources {
             }).await?;
         }
 
+        // and get their attacks back
+        for unit in units {
+            self.ready_unit(unit, communicator).await?;
+        }
+
         let hero = match self.current_turn {
             PlayerId::Player1 => &mut self.player_1.hero,
             PlayerId::Player2 => &mut self.player_2.hero,
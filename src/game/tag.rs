@@ -1,9 +1,12 @@
 use color_eyre::eyre::ContextCompat;
 use color_eyre::Result;
+use crate::game::tokens::counter_registry::CounterRegistry;
 use crate::game::tokens::token_deserializer::{TokenData, TokenCategory};
 
-use crate::game::tokens::token_instance::TokenInstance;
+use crate::game::tokens::text_template;
+use crate::game::tokens::token_instance::{TokenInstance, UnitStats};
 use crate::game::prompts::PromptType;
+use crate::game::game_over::GameSummary;
 use crate::game::id_types::{TokenInstanceId, LocationId, PlayerId, PromptInstanceId, ServerInstanceId};
 
 pub enum Tag {
@@ -12,16 +15,33 @@ pub enum Tag {
     F32(f32),
     String(String),
     TokenInstanceData(TokenInstance),
+    HiddenTokenInstanceData(TokenInstance),
     TokenData(TokenData),
     TokenBehaviors(TokenInstance),
+    HiddenTokenBehaviors,
+    TokenCounters(Vec<(String, String, i32)>),
+    TypeNames(Vec<(String, String)>),
     ServerInstanceId(ServerInstanceId),
     TokenInstanceId(TokenInstanceId),
     LocationId(LocationId),
     PromptInstanceId(PromptInstanceId),
     PromptType(PromptType),
+    GameSummary(GameSummary),
 }
 
 impl Tag {
+    pub fn visible_token_instance_data(token: TokenInstance, viewer: PlayerId) -> Tag {
+        if token.is_visible_to(viewer) { Tag::TokenInstanceData(token) } else { Tag::HiddenTokenInstanceData(token) }
+    }
+
+    pub fn visible_token_behaviors(token: TokenInstance, viewer: PlayerId) -> Tag {
+        if token.is_visible_to(viewer) { Tag::TokenBehaviors(token) } else { Tag::HiddenTokenBehaviors }
+    }
+
+    pub fn visible_token_counters(token: TokenInstance, viewer: PlayerId, counter_registry: &CounterRegistry) -> Tag {
+        Tag::TokenCounters(visible_counters(&token, viewer, counter_registry))
+    }
+
     pub fn build(self) -> Result<String> {
         Ok(format!("//{}/!", (match self {
             Tag::Player(p) => format!("{}", p as u32),
@@ -32,20 +52,20 @@ impl Tag {
                 let id = c.token_data.id.clone();
                 let name = format!("{} ({})", c.token_data.name.clone(), c.token_data.cost);
                 let description = c.token_data.description.clone().unwrap_or("".to_string()); // Todo: Is this the correct method for a default?
+                let description = text_template::expand_stats(&description, c.cost, &c.current_stats);
                 let cost = c.cost;
                 let mut health = c.current_stats.health;
                 let mut attack = c.current_stats.attack;
                 let mut defense = c.current_stats.defense;
                 let types = c.token_types.join(", ");
-                let token_category = match &c.token_data.token_category {
-                    TokenCategory::Hero { .. } => 0,
-                    TokenCategory::Landscape { .. } => 1,
-                    TokenCategory::Unit { .. } => 2,
-                    TokenCategory::Item => 3,
-                    TokenCategory::Command => 4,
-                };
+                let token_category = category_index(&c.token_data.token_category);
                 format!("{id};;{token_category};;{name};;{description};;{cost};;{health};;{defense};;{attack};;{types};;")
             },
+            Tag::HiddenTokenInstanceData(c) => {
+                // Only the category is shown, the client needs it to lay out the placeholder
+                let token_category = category_index(&c.token_data.token_category);
+                format!("hidden;;{token_category};;Hidden;;;;0;;0;;0;;0;;;;")
+            },
             Tag::TokenData(c) => {
                 let id = c.id.clone();
                 let name = format!("{} ({})", c.name, c.cost);
@@ -62,35 +82,85 @@ impl Tag {
                         0
                     },
                     TokenCategory::Landscape { .. } => 1,
-                    TokenCategory::Unit { health: h, defense: d, attack: a } => {
+                    TokenCategory::Unit { health: h, defense: d, attack: a, .. } => {
                         health = h;
                         defense = d;
                         attack = a;
                         2
                     },
                     TokenCategory::Item => 3,
-                    TokenCategory::Command => 4,
+                    TokenCategory::Command { .. } => 4,
                 };
+                let description = text_template::expand_stats(&description, cost, &UnitStats { health, defense, attack });
                 format!("{id};;{token_category};;{name};;{description};;{cost};;{health};;{defense};;{attack};;{types};;")
             },
             Tag::TokenBehaviors(c) => {
                 let mut string_to_send = String::new();
                 for behavior in c.behaviors {
                     if let Some(name) = behavior.name {
-                        string_to_send = format!("{}{};;{};;", string_to_send, name, behavior.description.unwrap_or("".to_string()));
+                        let description = text_template::expand_stats(&behavior.description.unwrap_or("".to_string()), c.cost, &c.current_stats);
+                        string_to_send = format!("{}{};;{};;", string_to_send, name, description);
                     }
                 }
                 string_to_send
             },
+            Tag::HiddenTokenBehaviors => String::new(),
+            Tag::TokenCounters(counters) => {
+                let mut string_to_send = String::new();
+                for (id, name, value) in counters {
+                    string_to_send = format!("{}{};;{};;{};;", string_to_send, id, name, value);
+                }
+                string_to_send
+            },
+            Tag::TypeNames(types) => {
+                let mut string_to_send = String::new();
+                for (id, name) in types {
+                    string_to_send = format!("{}{};;{};;", string_to_send, id, name);
+                }
+                string_to_send
+            },
             Tag::ServerInstanceId(c) => format!("{}", c),
             Tag::TokenInstanceId(c) => format!("{}", c),
             Tag::LocationId(c) => format!("{}", c),
             Tag::PromptInstanceId(id) => format!("{}", id),
             Tag::PromptType(t) => format!("{:?}", t),
+            Tag::GameSummary(summary) => {
+                let mut string_to_send = format!("{};;{};;", summary.rounds, summary.seed);
+                for player in summary.players {
+                    string_to_send = format!("{}{};;{};;{};;{};;{};;", string_to_send, player.player_id as u32, player.hero_health, player.tokens_in_set, player.tokens_in_hand, player.tokens_in_graveyard);
+                }
+                string_to_send
+            },
         })))
     }
 }
 
+/// Counters as (id, display name, value), sorted by id. Hidden tokens show no counters.
+pub fn visible_counters(token: &TokenInstance, viewer: PlayerId, counter_registry: &CounterRegistry) -> Vec<(String, String, i32)> {
+    if token.is_visible_to(viewer) == false {
+        return Vec::new();
+    }
+
+    let mut counters = token.counters.iter()
+        .map(|(id, value)| {
+            let name = counter_registry.get_data(id).map_or(id.clone(), |counter| counter.name.clone());
+            (id.clone(), name, *value)
+        })
+        .collect::<Vec<_>>();
+    counters.sort();
+    counters
+}
+
+pub fn category_index(category: &TokenCategory) -> u32 {
+    match category {
+        TokenCategory::Hero { .. } => 0,
+        TokenCategory::Landscape { .. } => 1,
+        TokenCategory::Unit { .. } => 2,
+        TokenCategory::Item => 3,
+        TokenCategory::Command { .. } => 4,
+    }
+}
+
 pub fn get_tag(tag: &str, data: &str) -> Result<String> {
     let start = data
         .find(&format!("/{tag}/"))
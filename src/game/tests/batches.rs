@@ -0,0 +1,108 @@
+use crate::game::game_communicator::GameCommunicator;
+use crate::game::id_types::{location_ids, PlayerId};
+use crate::game::instruction::InstructionToClient;
+use crate::game::prompts::PromptType;
+use crate::game::tests::harness::{TestMatch, GOLEM_SET};
+
+/// The instructions of each batch a seat received, by batch number.
+fn batches(instructions: &[InstructionToClient]) -> Vec<(u64, Vec<InstructionToClient>)> {
+    let mut batches = Vec::new();
+    let mut open: Option<(u64, Vec<InstructionToClient>)> = None;
+    for instruction in instructions {
+        match instruction {
+            InstructionToClient::BeginBatch { batch } => {
+                assert!(open.is_none(), "batch {} started inside another batch", batch);
+                open = Some((*batch, Vec::new()));
+            }
+            InstructionToClient::EndBatch { batch } => {
+                let (begun, contents) = open.take().expect("batch ended without being started");
+                assert_eq!(begun, *batch);
+                batches.push((begun, contents));
+            }
+            instruction => if let Some((_, contents)) = &mut open { contents.push(instruction.clone()) },
+        }
+    }
+    assert!(open.is_none(), "batch was never ended");
+    batches
+}
+
+#[tokio::test]
+async fn one_action_arrives_as_one_batch() {
+    let mut test_match = TestMatch::start(27, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    test_match.output.clear();
+
+    let golem = test_match.summon(first, "rock_golem", 0).await.unwrap();
+    let slot = location_ids::player_field_location_id(first, 0);
+
+    for player_id in [first, first.opponent()] {
+        let instructions = test_match.output.instructions_for(player_id);
+        let received = batches(&instructions);
+        assert_eq!(received.len(), 1);
+        assert_eq!(instructions.len(), received[0].1.len() + 2);
+        assert!(received[0].1.iter().any(|instruction| matches!(instruction, InstructionToClient::MoveToken { token, to } if *token == golem && *to == slot)));
+    }
+
+    let batch = batches(&test_match.output.instructions_for(first))[0].0;
+    test_match.output.clear();
+    test_match.pass_turn(first).await.unwrap();
+    assert_eq!(batches(&test_match.output.instructions_for(first))[0].0, batch + 1);
+}
+
+#[tokio::test]
+async fn prompt_updates_arrive_in_the_batch_of_their_action() {
+    let mut test_match = TestMatch::start(29, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let golem = test_match.find_in_hand(first, "rock_golem").unwrap();
+    test_match.run_action(golem, r#"
+        then = "add_behavior"
+        with = { target = "this", behavior = "generic.haste" }
+    "#).await.unwrap();
+
+    // A hasted golem can attack right away, so summoning it adds a prompt and passing the turn removes it
+    let only_batch = |test_match: &TestMatch| {
+        let instructions = test_match.output.instructions_for(first);
+        let mut received = batches(&instructions);
+        assert_eq!(received.len(), 1);
+        assert_eq!(instructions.len(), received[0].1.len() + 2);
+        received.remove(0).1
+    };
+    test_match.output.clear();
+    test_match.move_token(first, golem, location_ids::player_field_location_id(first, 0)).await.unwrap();
+    assert!(only_batch(&test_match).iter().any(|instruction| matches!(instruction, InstructionToClient::AddPrompt { prompt_type: PromptType::SelectToken(token), .. } if *token == golem)));
+
+    test_match.output.clear();
+    test_match.pass_turn(first).await.unwrap();
+    assert!(only_batch(&test_match).iter().any(|instruction| matches!(instruction, InstructionToClient::RemovePrompt { .. })));
+}
+
+#[tokio::test]
+async fn starting_a_game_arrives_as_one_batch() {
+    let test_match = TestMatch::start(28, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+
+    for player_id in [PlayerId::Player1, PlayerId::Player2] {
+        let instructions = test_match.output.instructions_for(player_id);
+        let received = batches(&instructions);
+        assert_eq!(received.len(), 1);
+        assert_eq!(instructions.len(), received[0].1.len() + 2);
+    }
+}
+
+#[tokio::test]
+async fn nested_batches_fold_into_the_outer_one() {
+    let (mut communicator, output) = GameCommunicator::headless();
+    communicator.begin_batch();
+    communicator.send_game_instruction_to(PlayerId::Player1, InstructionToClient::Resync).await.unwrap();
+    communicator.begin_batch();
+    communicator.send_game_instruction_to(PlayerId::Player1, InstructionToClient::Resync).await.unwrap();
+    communicator.end_batch().await.unwrap();
+    assert!(matches!(output.instructions_for(PlayerId::Player1).last(), Some(InstructionToClient::Resync)));
+    communicator.end_batch().await.unwrap();
+
+    let received = batches(&output.instructions_for(PlayerId::Player1));
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].0, 1);
+    assert_eq!(received[0].1.len(), 2);
+    // Nothing was sent to the other seat, so it doesn't see an empty batch either
+    assert!(output.instructions_for(PlayerId::Player2).is_empty());
+}
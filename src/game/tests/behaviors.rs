@@ -0,0 +1,302 @@
+use std::fs;
+
+use crate::game::id_types::{location_ids, LocationId, PlayerId, TokenInstanceId};
+use crate::game::instruction::InstructionToClient;
+use crate::game::tests::harness::{TestMatch, write_data_directory, GOLEM_SET, LARGE_GOLEM_SET};
+use crate::game::tokens::text_template;
+use crate::game::tokens::token_instance::UnitStats;
+use crate::game::tokens::token_registry::TokenRegistry;
+
+#[tokio::test]
+async fn counters_can_be_set_modified_and_checked() {
+    let mut test_match = TestMatch::start(20, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let golem = test_match.summon(first, "rock_golem", 0).await.unwrap();
+
+    test_match.run_action(golem, r#"
+        then = "set_counter"
+        with = { target = "this", counter = "spectral_transition", amount = 3 }
+    "#).await.unwrap();
+    test_match.run_action(golem, r#"
+        then = "modify_counter"
+        with = { target = "this", counter = "spectral_transition", amount = -1 }
+    "#).await.unwrap();
+
+    assert_eq!(test_match.token(golem).counters.get("spectral_transition"), Some(&2));
+    assert!(test_match.check_condition(golem, r#"
+        check = "counter"
+        with = { target = "this", counter = "spectral_transition", value = 2 }
+    "#).await.unwrap());
+    assert!(test_match.check_condition(golem, r#"
+        check = "counter"
+        with = { target = "this", counter = "spectral_transition", condition = "greater", value = 2 }
+    "#).await.unwrap() == false);
+    assert!(test_match.was_sent(first.opponent(), |instruction| matches!(instruction, InstructionToClient::UpdateCounters { token_data } if token_data.instance_id == golem)));
+}
+
+#[tokio::test]
+async fn shared_behaviors_can_be_added_and_removed_by_id() {
+    let mut test_match = TestMatch::start(21, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let golem = test_match.summon(first, "rock_golem", 0).await.unwrap();
+
+    test_match.run_action(golem, r#"
+        then = "add_behavior"
+        with = { target = "this", behavior = "generic.protected" }
+    "#).await.unwrap();
+    assert!(test_match.token(golem).behaviors.iter().any(|behavior| behavior.id.as_deref() == Some("generic.protected")));
+    assert!(test_match.was_sent(first.opponent(), |instruction| matches!(instruction, InstructionToClient::UpdateBehaviors { token_data } if token_data.behaviors.len() == 1)));
+
+    test_match.run_action(golem, r#"
+        then = "remove_behavior"
+        with = { target = "this", id = "generic.protected" }
+    "#).await.unwrap();
+    assert!(test_match.token(golem).behaviors.is_empty());
+
+    assert!(test_match.run_action(golem, r#"
+        then = "add_behavior"
+        with = { target = "this", behavior = "generic.unknown" }
+    "#).await.is_err());
+}
+
+#[tokio::test]
+async fn units_summoned_by_effects_cost_nothing() {
+    let mut test_match = TestMatch::start(36, &LARGE_GOLEM_SET, &LARGE_GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let golem = test_match.summon(first, "rock_golem", 0).await.unwrap();
+    test_match.game.resources.get_player_mut(first).thaum = 0;
+
+    test_match.run_action(golem, r#"
+        then = "summon"
+        with = { target = "this", token = "rock_golem" }
+    "#).await.unwrap();
+
+    let field = test_match.game.resources.get_tokens_on_field().iter()
+        .filter(|token| token.owner == first && token.token_data.id == "rock_golem")
+        .count();
+    assert_eq!(field, 2);
+    assert_eq!(test_match.thaum(first), 0);
+}
+
+#[tokio::test]
+async fn triggers_only_fire_in_their_zones() {
+    let mut test_match = TestMatch::start(22, &LARGE_GOLEM_SET, &LARGE_GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let in_hand = test_match.give(first, "rock_golem").await.unwrap();
+    let in_play_only = test_match.give(first, "rock_golem").await.unwrap();
+    let behavior = |zone: &str| format!(r#"
+        [[trigger]]
+        {}
+        when = "owned:turn_started"
+
+        [[action]]
+        then = "modify_counter"
+        with = {{ target = "this", counter = "spectral_transition", amount = 1 }}
+    "#, zone);
+    test_match.add_behavior(in_hand, &behavior(r#"in = ["hand", "field"]"#)).unwrap();
+    test_match.add_behavior(in_play_only, &behavior("")).unwrap();
+
+    test_match.pass_turn(first).await.unwrap();
+    test_match.pass_turn(first.opponent()).await.unwrap();
+
+    assert_eq!(test_match.token(in_hand).counters.get("spectral_transition"), Some(&1));
+    assert_eq!(test_match.token(in_play_only).counters.get("spectral_transition"), None);
+}
+
+#[test]
+fn shared_behaviors_are_part_of_every_instance() {
+    let directory = write_data_directory("shared-behaviors", "", SHARED_BEHAVIORS, r#"
+        category = "unit"
+        name = "Hasty Golem"
+        cost = 1
+        types = []
+        health = 1
+        shared_behaviors = ["test.hasty"]
+    "#);
+    let registry = TokenRegistry::from_data_directory(directory.to_str().unwrap());
+    fs::remove_dir_all(&directory).unwrap();
+
+    let token = registry.unwrap().instance_token("test_token", TokenInstanceId(1), LocationId(100), PlayerId::Player1).unwrap();
+    assert_eq!(token.behaviors.len(), 1);
+    assert_eq!(token.behaviors[0].id.as_deref(), Some("test.hasty"));
+}
+
+#[test]
+fn unknown_shared_behaviors_are_rejected_at_load() {
+    let directory = write_data_directory("unknown-behavior", "", SHARED_BEHAVIORS, r#"
+        category = "unit"
+        name = "Hasty Golem"
+        cost = 1
+        types = []
+        health = 1
+        shared_behaviors = ["test.unknown"]
+    "#);
+    let registry = TokenRegistry::from_data_directory(directory.to_str().unwrap());
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert!(registry.is_err());
+}
+
+#[test]
+fn unknown_counters_are_rejected_at_load() {
+    let directory = write_data_directory("unknown-counter", "[[counter]]\nid = \"known\"\nname = \"Known\"\n", "", r#"
+        category = "unit"
+        name = "Counting Golem"
+        cost = 1
+        types = []
+        health = 1
+
+        [[behavior]]
+            [[behavior.trigger]]
+            when = "this:has_been_summoned"
+
+            [[behavior.action]]
+            then = "set_counter"
+            with = { target = "this", counter = "unknown", value = 1 }
+    "#);
+    let registry = TokenRegistry::from_data_directory(directory.to_str().unwrap());
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert!(registry.is_err());
+}
+
+#[test]
+fn descriptions_are_expanded_at_load() {
+    let registry = TokenRegistry::from_data_directory("data").unwrap();
+
+    let hidden = registry.behavior_registry.get_data("generic.hidden").unwrap();
+    assert!(hidden.description.as_ref().unwrap().starts_with("If a <b>Unit</b> on the field is hidden"));
+    let skull = registry.get_data("ancient_skull").unwrap();
+    assert_eq!(skull.behaviors[0].description.as_deref(), Some("When a Phantom is equipped to this token, replace it with a phantom_skull"));
+    assert!(registry.warnings.contains(&"Token ancient_skull refers to an unknown token: phantom_skull".to_string()));
+}
+
+#[test]
+fn unknown_placeholders_are_rejected_at_load() {
+    for description in ["A {{keyword:missing}}", "A {{type:missing}}", "A {{stat:speed}}", "A {{missing:unit}}", "A {{keyword:unit"] {
+        let directory = write_data_directory("unknown-placeholder", "", "", &format!("category = \"item\"\nname = \"Item\"\ncost = 1\ntypes = []\ndescription = \"{}\"\n", description));
+        let registry = TokenRegistry::from_data_directory(directory.to_str().unwrap());
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(registry.is_err(), "{} was accepted", description);
+    }
+}
+
+#[test]
+fn stats_are_filled_in_when_sent() {
+    let stats = UnitStats { health: 3, defense: 1, attack: 7 };
+    assert_eq!(text_template::expand_stats("Deals {{stat:attack}} damage, costs {{stat:cost}}", 4, &stats), "Deals 7 damage, costs 4");
+}
+
+#[test]
+fn unknown_types_are_reported_as_warnings() {
+    let directory = write_data_directory("unknown-type", "", "", r#"
+        category = "unit"
+        name = "Odd Golem"
+        cost = 1
+        types = ["creature", "odd"]
+        health = 1
+
+        [[behavior]]
+            [[behavior.trigger]]
+            when = "owned:has_been_summoned"
+            and = { check = "count", with = { filter = { contains_types = ["stranger"] }, condition = "greater", count = 0 } }
+
+            [[behavior.action]]
+            then = "draw_token"
+            with = { target = "owner" }
+    "#);
+    let registry = TokenRegistry::from_data_directory(directory.to_str().unwrap());
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(registry.unwrap().warnings, vec![
+        "Token test_token uses an unknown type: odd".to_string(),
+        "Token test_token uses an unknown type: stranger".to_string(),
+    ]);
+}
+
+const SHARED_BEHAVIORS: &str = r#"
+[[behavior]]
+    id = "test.hasty"
+    name = "Hasty"
+
+    [[behavior.trigger]]
+    when = "this:was_summoned"
+
+    [[behavior.action]]
+    then = "draw_token"
+    with = { target = "owner" }
+"#;
+
+#[tokio::test]
+async fn protected_units_cancel_attacks_on_them() {
+    let mut test_match = TestMatch::start(23, &LARGE_GOLEM_SET, &LARGE_GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let second = first.opponent();
+    let attacker = test_match.summon(first, "rock_golem", 0).await.unwrap();
+    test_match.pass_turn(first).await.unwrap();
+    let defender = test_match.summon(second, "rock_golem", 0).await.unwrap();
+    test_match.run_action(defender, r#"
+        then = "add_behavior"
+        with = { target = "this", behavior = "generic.protected" }
+    "#).await.unwrap();
+    test_match.pass_turn(second).await.unwrap();
+
+    test_match.attack(first, attacker, defender).await.unwrap();
+
+    assert_eq!(test_match.token(defender).current_stats.defense, 5);
+    assert_eq!(test_match.token(attacker).current_stats.defense, 5);
+    assert!(test_match.was_sent(first, |instruction| matches!(instruction, InstructionToClient::Cancelled { reason } if reason == "Cancelled by Rock Golem (Protected)")));
+}
+
+#[tokio::test]
+async fn cancelled_summons_stay_in_hand() {
+    let mut test_match = TestMatch::start(24, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let guard = test_match.give(first, "rock_golem").await.unwrap();
+    let golem = test_match.give(first, "rock_golem").await.unwrap();
+    test_match.move_token(first, guard, location_ids::player_field_location_id(first, 0)).await.unwrap();
+    test_match.add_behavior(guard, r#"
+        name = "No summon allowed"
+        [[trigger]]
+        when = "owned:will_be_summoned"
+
+        [[action]]
+        then = "cancel"
+    "#).unwrap();
+    let thaum = test_match.thaum(first);
+
+    test_match.move_token(first, golem, location_ids::player_field_location_id(first, 1)).await.unwrap();
+
+    let hand = test_match.game.resources.get_player(first).hand;
+    assert_eq!(test_match.token(golem).location, hand);
+    assert_eq!(test_match.thaum(first), thaum);
+    assert!(test_match.was_sent(first, |instruction| matches!(instruction, InstructionToClient::MoveToken { token, to } if *token == golem && *to == hand)));
+    assert!(test_match.was_sent(first, |instruction| matches!(instruction, InstructionToClient::Cancelled { .. })));
+}
+
+#[tokio::test]
+async fn cancelled_effect_summons_leave_the_field() {
+    let mut test_match = TestMatch::start(37, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let guard = test_match.summon(first, "rock_golem", 0).await.unwrap();
+    test_match.add_behavior(guard, r#"
+        name = "No summon allowed"
+        [[trigger]]
+        when = "owned:will_be_summoned"
+
+        [[action]]
+        then = "cancel"
+    "#).unwrap();
+
+    test_match.run_action(guard, r#"
+        then = "summon"
+        with = { target = "this", token = "rock_golem" }
+    "#).await.unwrap();
+
+    let field = test_match.game.resources.get_tokens_on_field().iter().map(|token| token.instance_id).collect::<Vec<_>>();
+    assert_eq!(field, vec!(guard));
+    let graveyard = test_match.game.resources.board.get_side(first).graveyard;
+    assert_eq!(test_match.tokens_in(graveyard).len(), 1);
+    assert!(test_match.was_sent(first, |instruction| matches!(instruction, InstructionToClient::Cancelled { .. })));
+}
@@ -0,0 +1,83 @@
+use crate::game::game_over::GameOverReason;
+use crate::game::id_types::PlayerId;
+use crate::game::instruction::InstructionToClient;
+use crate::game::output_sink::OutgoingMessage;
+use crate::game::prompts::PromptType;
+use crate::game::tests::harness::{TestMatch, GOLEM_SET};
+
+async fn conceded_match(seed: u64) -> (TestMatch, PlayerId) {
+    let mut test_match = TestMatch::start(seed, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    test_match.send(first, "concede", "").await.unwrap();
+    (test_match, first)
+}
+
+#[tokio::test]
+async fn conceding_hands_the_win_to_the_opponent() {
+    let (test_match, first) = conceded_match(30).await;
+
+    let game_over = test_match.game.resources.game_over.as_ref().unwrap();
+    assert_eq!(game_over.winner, first.opponent());
+    assert_eq!(game_over.reason, GameOverReason::Concede);
+    assert_eq!(game_over.summary.players.iter().map(|player| player.tokens_in_hand).sum::<usize>(), 11);
+    assert!(test_match.was_sent(first.opponent(), |instruction| matches!(instruction, InstructionToClient::EndGame { reason: GameOverReason::Concede, .. })));
+    assert!(test_match.game.current_callback.is_none());
+}
+
+#[tokio::test]
+async fn conceding_closes_prompts_that_would_have_to_be_answered() {
+    let mut test_match = TestMatch::start(31, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let golem = test_match.summon_hasted(first, "rock_golem", 0).await.unwrap();
+    test_match.callback(first, PromptType::SelectToken(golem)).await.unwrap();
+
+    test_match.send(first, "concede", "").await.unwrap();
+    assert_eq!(test_match.game.resources.game_over.as_ref().map(|game_over| game_over.winner), Some(first.opponent()));
+}
+
+#[tokio::test]
+async fn games_that_are_over_refuse_further_moves() {
+    let (mut test_match, first) = conceded_match(32).await;
+    let sent_before = test_match.output.messages().len();
+
+    test_match.pass_turn(test_match.current_turn()).await.unwrap();
+    test_match.send(first.opponent(), "concede", "").await.unwrap();
+
+    assert_eq!(test_match.game.resources.game_over.as_ref().map(|game_over| game_over.reason), Some(GameOverReason::Concede));
+    let refused = test_match.output.messages().into_iter().skip(sent_before)
+        .filter(|(_, message)| matches!(message, OutgoingMessage::Instruction(InstructionToClient::Refused { reason }) if reason == "The game is over"))
+        .count();
+    assert_eq!(refused, 2);
+}
+
+#[tokio::test]
+async fn rematches_start_once_both_players_ask() {
+    let mut test_match = TestMatch::start(33, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    test_match.summon(first, "rock_golem", 0).await.unwrap();
+    test_match.send(first, "concede", "").await.unwrap();
+
+    test_match.send(first, "rematch", "").await.unwrap();
+    test_match.send(first, "rematch", "").await.unwrap();
+    assert!(test_match.game.resources.game_over.is_some());
+    assert!(test_match.was_sent(first.opponent(), |instruction| matches!(instruction, InstructionToClient::RematchRequested { player_id } if *player_id == first)));
+
+    test_match.send(first.opponent(), "rematch", "").await.unwrap();
+    assert!(test_match.game.resources.game_over.is_none());
+    assert_eq!(test_match.game.resources.round, 1);
+    assert!(test_match.game.resources.get_tokens_on_field().is_empty());
+    assert_eq!(test_match.game.resources.token_instances.len(), GOLEM_SET.len() * 2);
+    assert!(test_match.game.resources.equipment_slot_owners.is_empty());
+    for player_id in [PlayerId::Player1, PlayerId::Player2] {
+        assert!(test_match.tokens_in(test_match.game.resources.get_player(player_id).hand).len() >= 5);
+    }
+}
+
+#[tokio::test]
+async fn rematches_need_a_finished_game() {
+    let mut test_match = TestMatch::start(34, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    test_match.send(PlayerId::Player1, "rematch", "").await.unwrap();
+
+    assert!(test_match.game.resources.game_over.is_none());
+    assert!(test_match.was_sent(PlayerId::Player1, |instruction| matches!(instruction, InstructionToClient::Refused { .. })));
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use color_eyre::eyre::ContextCompat;
+use color_eyre::Result;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::game::game_communicator::GameCommunicator;
+use crate::game::game_context::{context_keys, ContextValue, GameContext};
+use crate::game::game_service::GameSession;
+use crate::game::id_types::{location_ids, LocationId, PlayerId, PromptInstanceId, TokenInstanceId};
+use crate::game::instruction::InstructionToClient;
+use crate::game::output_sink::MemoryOutput;
+use crate::game::prompts::PromptType;
+use crate::game::tokens::token_deserializer::{TokenBehavior, TokenBehaviorAction, TokenBehaviorTriggerAnd};
+use crate::game::tokens::token_instance::TokenInstance;
+use crate::game::tokens::token_registry::TokenRegistry;
+
+/// Decks of a hero, a landscape and rock golems, enough for most tests.
+pub const GOLEM_SET: [&str; 8] = ["specter_overlord", "farmland", "rock_golem", "rock_golem", "rock_golem", "rock_golem", "rock_golem", "rock_golem"];
+pub const LARGE_GOLEM_SET: [&str; 14] = ["specter_overlord", "farmland", "rock_golem", "rock_golem", "rock_golem", "rock_golem", "rock_golem", "rock_golem", "rock_golem", "rock_golem", "rock_golem", "rock_golem", "rock_golem", "rock_golem"];
+
+/// Plays a match in memory by feeding it the same messages a client would send over the websocket.
+pub struct TestMatch {
+    pub game: GameSession,
+    pub output: MemoryOutput,
+    pub seed: u64,
+}
+
+impl TestMatch {
+    pub fn new(seed: u64) -> Self {
+        let (communicator, output) = GameCommunicator::headless();
+        Self {
+            game: GameSession::new(communicator),
+            output,
+            seed,
+        }
+    }
+
+    /// Starts a match from two decks of token ids, each containing a hero and a landscape.
+    pub async fn start(seed: u64, set_1: &[&str], set_2: &[&str]) -> Result<Self> {
+        let mut test_match = Self::new(seed);
+        let seed = test_match.seed;
+        test_match.send(PlayerId::Player1, "start_game", &format!("/seed/{}/!seed//set1/{}/!set1//set2/{}/!set2/", seed, set_1.join(","), set_2.join(","))).await?;
+        Ok(test_match)
+    }
+
+    pub async fn send(&mut self, player_id: PlayerId, instruction: &str, data: &str) -> Result<()> {
+        // Goes through the communicator like the game service does, so the message is recorded
+        self.game.communicator.push_message(player_id, Message::Text(format!("{}|{}", instruction, data)));
+        let (player_id, message) = self.game.communicator.read_message().await?;
+        self.game.handle_message(player_id, message.to_text()?).await
+    }
+
+    pub async fn move_token(&mut self, player_id: PlayerId, token: TokenInstanceId, location: LocationId) -> Result<()> {
+        self.send(player_id, "move_token", &format!("/token/{}/!token//location/{}/!location/", token, location)).await
+    }
+
+    pub async fn pass_turn(&mut self, player_id: PlayerId) -> Result<()> {
+        self.send(player_id, "pass_turn", "").await
+    }
+
+    /// Answers the open prompt of the given type.
+    pub async fn callback(&mut self, player_id: PlayerId, prompt_type: PromptType) -> Result<()> {
+        let prompt_instance_id = *self.open_prompts(player_id).get(&prompt_type).context(format!("No open prompt {:?} for {}", prompt_type, player_id))?;
+        self.send(player_id, "callback", &format!("/callback_id/{}/!callback_id//value/true/!value/", prompt_instance_id)).await
+    }
+
+    /// Summons the first unit in hand with the given id to a field slot.
+    pub async fn summon(&mut self, player_id: PlayerId, id: &str, slot: u64) -> Result<TokenInstanceId> {
+        let token = self.find_in_hand(player_id, id)?;
+        self.move_token(player_id, token, location_ids::player_field_location_id(player_id, slot)).await?;
+        Ok(token)
+    }
+
+    /// Summons a unit with haste, so it can attack during the turn it was summoned in.
+    pub async fn summon_hasted(&mut self, player_id: PlayerId, id: &str, slot: u64) -> Result<TokenInstanceId> {
+        let token = self.find_in_hand(player_id, id)?;
+        self.run_action(token, r#"
+            then = "add_behavior"
+            with = { target = "this", behavior = "generic.haste" }
+        "#).await?;
+        self.move_token(player_id, token, location_ids::player_field_location_id(player_id, slot)).await?;
+        Ok(token)
+    }
+
+    pub async fn attack(&mut self, player_id: PlayerId, attacker: TokenInstanceId, defender: TokenInstanceId) -> Result<()> {
+        self.callback(player_id, PromptType::SelectToken(attacker)).await?;
+        self.callback(player_id, PromptType::AttackToken(defender)).await
+    }
+
+    /// Runs a single behavior action, written as it would appear in a token file, on behalf of a token.
+    /// Groups the action queues are processed afterwards.
+    pub async fn run_action(&mut self, this: TokenInstanceId, action: &str) -> Result<GameContext> {
+        let action: TokenBehaviorAction = toml::from_str(action)?;
+        let mut context = self.behavior_context(this);
+        action.run(&mut context, &mut self.game.resources, &mut self.game.state, &mut self.game.communicator).await?;
+        self.game.state.process(&mut self.game.resources, &mut self.game.communicator).await?;
+        Ok(context)
+    }
+
+    /// Gives a token an extra behavior, written as it would appear in a token file.
+    pub fn add_behavior(&mut self, this: TokenInstanceId, behavior: &str) -> Result<()> {
+        let behavior: TokenBehavior = toml::from_str(behavior)?;
+        self.game.resources.token_instances.get_mut(&this).context("Token not found")?.behaviors.push(behavior);
+        Ok(())
+    }
+
+    /// Evaluates a trigger condition, written as it would appear in a token file, on behalf of a token.
+    pub async fn check_condition(&mut self, this: TokenInstanceId, condition: &str) -> Result<bool> {
+        let condition: TokenBehaviorTriggerAnd = toml::from_str(condition)?;
+        let context = self.behavior_context(this);
+        condition.check(&context, &mut self.game.resources, &mut self.game.communicator).await
+    }
+
+    fn behavior_context(&self, this: TokenInstanceId) -> GameContext {
+        let mut context = GameContext::new();
+        context.insert(context_keys::OWNER, ContextValue::PlayerId(self.token(this).owner));
+        context.insert(context_keys::TRIGGER_THIS, ContextValue::TokenInstanceId(this));
+        context.insert(context_keys::ACTION_THIS, ContextValue::TokenInstanceId(this));
+        context
+    }
+
+    /// Prompts that were added for a player and not removed yet, replayed from the output.
+    pub fn open_prompts(&self, player_id: PlayerId) -> HashMap<PromptType, PromptInstanceId> {
+        let mut prompts = HashMap::new();
+        for instruction in self.output.instructions_for(player_id) {
+            match instruction {
+                InstructionToClient::AddPrompt { prompt_instance_id, prompt_type } => { prompts.insert(prompt_type, prompt_instance_id); }
+                InstructionToClient::RemovePrompt { prompt_instance_id } => prompts.retain(|_, id| *id != prompt_instance_id),
+                _ => {}
+            }
+        }
+        prompts
+    }
+
+    /// Creates a token straight in a player's hand, so a test does not depend on what gets drawn.
+    pub async fn give(&mut self, player_id: PlayerId, id: &str) -> Result<TokenInstanceId> {
+        let hand = self.game.resources.get_player(player_id).hand;
+        self.game.resources.create_token(id, hand, player_id, &mut self.game.communicator).await
+    }
+
+    /// Creates a token written for the test in a player's hand, for tokens the shipped data doesn't have.
+    pub async fn give_fixture(&mut self, player_id: PlayerId, name: &str, token: &str) -> Result<TokenInstanceId> {
+        let directory = write_data_directory(name, "", "", token);
+        let registry = TokenRegistry::from_data_directory(directory.to_str().context("Fixture path is not valid UTF-8")?);
+        fs::remove_dir_all(&directory)?;
+        let registry = registry?;
+        let hand = self.game.resources.get_player(player_id).hand;
+        let token = registry.instance_token("test_token", TokenInstanceId(self.game.resources.rng.json_safe_u64()), hand, player_id)?;
+        self.game.resources.add_token(token, hand, &mut self.game.communicator).await
+    }
+
+    pub fn find_in_hand(&self, player_id: PlayerId, id: &str) -> Result<TokenInstanceId> {
+        let hand = self.game.resources.get_player(player_id).hand;
+        self.tokens_in(hand).into_iter()
+            .find(|token| self.token(*token).token_data.id == id)
+            .context(format!("{} has no {} in hand", player_id, id))
+    }
+
+    pub fn tokens_in(&self, location: LocationId) -> Vec<TokenInstanceId> {
+        self.game.resources.locations.get(&location).unwrap().get_tokens()
+    }
+
+    pub fn token(&self, token: TokenInstanceId) -> &TokenInstance {
+        self.game.resources.token_instances.get(&token).unwrap()
+    }
+
+    pub fn hero(&self, player_id: PlayerId) -> TokenInstanceId {
+        self.game.resources.get_player(player_id).hero
+    }
+
+    pub fn thaum(&self, player_id: PlayerId) -> u32 {
+        self.game.resources.get_player(player_id).thaum
+    }
+
+    pub fn current_turn(&self) -> PlayerId {
+        self.game.resources.current_turn
+    }
+
+    pub fn was_sent(&self, player_id: PlayerId, predicate: impl Fn(&InstructionToClient) -> bool) -> bool {
+        self.output.instructions_for(player_id).iter().any(predicate)
+    }
+}
+
+/// Writes a throwaway data directory with a single token called `test_token`.
+pub fn write_data_directory(name: &str, counters: &str, behaviors: &str, token: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("landmark-{}-{}", name, std::process::id()));
+    fs::create_dir_all(directory.join("tokens")).unwrap();
+    fs::write(directory.join("counters.toml"), counters).unwrap();
+    fs::write(directory.join("behaviors.toml"), behaviors).unwrap();
+    fs::write(directory.join("keywords.toml"), "[[keyword]]\nid = \"unit\"\ntext = \"<b>Unit</b>\"\n").unwrap();
+    fs::write(directory.join("types.toml"), "[[type]]\nid = \"creature\"\nname = \"Creature\"\n").unwrap();
+    fs::write(directory.join("tokens/test_token.toml"), token).unwrap();
+    directory
+}
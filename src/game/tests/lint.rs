@@ -0,0 +1,125 @@
+use std::fs;
+
+use crate::game::tests::harness::write_data_directory;
+use crate::game::tokens::token_linter::{lint, LintIssue, LintKind, LintReport, LintSeverity};
+use crate::game::tokens::token_registry::TokenRegistry;
+use crate::TOKEN_REGISTRY;
+
+fn lint_token(name: &str, token: &str) -> Vec<LintIssue> {
+    let directory = write_data_directory(name, "", "", token);
+    let registry = TokenRegistry::from_data_directory(directory.to_str().unwrap());
+    fs::remove_dir_all(&directory).unwrap();
+    lint(&registry.unwrap())
+}
+
+#[tokio::test]
+async fn shipped_tokens_only_refer_to_known_tokens() {
+    let issues = lint(&*TOKEN_REGISTRY.lock().await);
+
+    // The Phantom Skull has not been designed yet, its only reference waits for it
+    let unknown = issues.iter().filter(|issue| issue.kind == LintKind::UnknownToken).collect::<Vec<_>>();
+    assert_eq!(unknown.len(), 1);
+    assert_eq!(unknown[0].token.as_deref(), Some("ancient_skull"));
+    assert!(unknown[0].message.contains("phantom_skull"));
+    assert!(issues.iter().any(|issue| issue.kind == LintKind::UnknownContextKey
+        && issue.token.as_deref() == Some("spiritual_bond")
+        && issue.message.contains("bound_unit")));
+}
+
+#[test]
+fn unknown_tokens_and_context_keys_are_errors() {
+    let issues = lint_token("lint-references", r#"
+        category = "unit"
+        name = "Lint Golem"
+        cost = 1
+        types = []
+        health = 1
+
+        [[behavior]]
+            name = "Broken"
+            [[behavior.trigger]]
+            when = "this:has_been_summoned"
+
+            [[behavior.action]]
+            then = "summon"
+            with = { target = { context = { key = "defender" } }, token = "missing_token" }
+
+        [[behavior]]
+            name = "Fine"
+            [[behavior.trigger]]
+            when = "this:will_be_attacked"
+
+            [[behavior.action]]
+            then = "destroy"
+            with = { target = { context = { key = "defender" } } }
+    "#);
+
+    assert_eq!(issues.len(), 2);
+    assert!(issues.iter().all(|issue| issue.severity == LintSeverity::Error && issue.behavior.as_deref() == Some("Broken")));
+    assert!(issues.iter().any(|issue| issue.kind == LintKind::UnknownToken && issue.message.contains("missing_token")));
+    assert!(issues.iter().any(|issue| issue.kind == LintKind::UnknownContextKey && issue.message.contains("defender")));
+}
+
+#[test]
+fn heroes_without_health_are_errors() {
+    let issues = lint_token("lint-hero", r#"
+        category = "hero"
+        name = "Lint Hero"
+        cost = 0
+        types = []
+        health = 0
+    "#);
+
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].kind, LintKind::ZeroHealthHero);
+    assert_eq!(issues[0].token.as_deref(), Some("test_token"));
+}
+
+#[test]
+fn landscapes_without_slots_are_errors() {
+    let issues = lint_token("lint-landscape", r#"
+        category = "landscape"
+        name = "Lint Landscape"
+        cost = 0
+        types = []
+        slots = []
+    "#);
+
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].kind, LintKind::EmptyLandscape);
+}
+
+#[test]
+fn data_that_fails_to_load_is_reported() {
+    let report = LintReport::from_data_directory("does-not-exist");
+
+    assert_eq!(report.errors, 1);
+    assert_eq!(report.issues[0].kind, LintKind::LoadFailed);
+    assert!(serde_json::to_string(&report).unwrap().contains("\"kind\":\"load_failed\""));
+}
+
+#[test]
+fn unknown_types_name_the_token_and_behavior_using_them() {
+    let issues = lint_token("lint-types", r#"
+        category = "unit"
+        name = "Odd Golem"
+        cost = 1
+        types = ["odd"]
+        health = 1
+
+        [[behavior]]
+            name = "Seeker"
+            [[behavior.trigger]]
+            when = "owned:has_been_summoned"
+            and = { check = "count", with = { filter = { contains_types = ["stranger"] }, condition = "greater", count = 0 } }
+
+            [[behavior.action]]
+            then = "draw_token"
+            with = { target = "owner" }
+    "#);
+
+    let unknown = issues.iter().filter(|issue| issue.kind == LintKind::UnknownType)
+        .map(|issue| (issue.token.as_deref(), issue.behavior.as_deref()))
+        .collect::<Vec<_>>();
+    assert_eq!(unknown, vec![(Some("test_token"), None), (Some("test_token"), Some("Seeker"))]);
+}
@@ -0,0 +1,460 @@
+use crate::game::game_over::GameOverReason;
+use crate::game::id_types::{location_ids, LocationId, PlayerId};
+use crate::game::instruction::InstructionToClient;
+use crate::game::animation_presets::AnimationPreset;
+use crate::game::output_sink::OutgoingMessage;
+use crate::game::prompts::PromptType;
+use crate::game::tests::harness::{TestMatch, GOLEM_SET, LARGE_GOLEM_SET};
+use crate::TOKEN_REGISTRY;
+
+const FLAME_SET: [&str; 8] = ["specter_overlord", "farmland", "flame_golem", "flame_golem", "flame_golem", "flame_golem", "flame_golem", "flame_golem"];
+const EQUIP_SET: [&str; 8] = ["specter_overlord", "farmland", "rock_golem", "rock_golem", "rock_golem", "bladesong_symphony", "bladesong_symphony", "bladesong_symphony"];
+const FIREBALL: &str = r#"
+category = "command"
+name = "Fireball"
+description = "Deal 3 damage to an opponent {{keyword:unit}}."
+cost = 2
+types = ["creature"]
+cast_target = { owned_by = "opponent" }
+
+[[behavior]]
+    name = "Fireball"
+    [[behavior.trigger]]
+    when = "this:has_cast"
+
+    [[behavior.action]]
+    then = "damage_unit"
+    with = { target = { context = { key = "cast_target" } }, amount = 3 }
+"#;
+
+#[tokio::test]
+async fn start_game_prepares_both_sides() {
+    let test_match = TestMatch::start(1, &GOLEM_SET, &FLAME_SET).await.unwrap();
+    let first = test_match.current_turn();
+
+    for player_id in [first, first.opponent()] {
+        let player = test_match.game.resources.get_player(player_id);
+        assert_eq!(test_match.tokens_in(location_ids::player_hero_location_id(player_id)), vec![player.hero]);
+        assert_eq!(test_match.tokens_in(location_ids::player_landscape_location_id(player_id)), vec![player.landscape]);
+        assert_eq!(test_match.game.resources.board.get_side(player_id).field.len(), 8);
+    }
+
+    // The first player drew an extra token at the start of their turn
+    assert_eq!(test_match.tokens_in(test_match.game.resources.get_player(first).hand).len(), 6);
+    assert_eq!(test_match.tokens_in(test_match.game.resources.get_player(first.opponent()).hand).len(), 5);
+    assert_eq!(test_match.thaum(first), 11);
+}
+
+async fn play_seeded_match(seed: u64) -> TestMatch {
+    let mut test_match = TestMatch::start(seed, &LARGE_GOLEM_SET, &LARGE_GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let second = first.opponent();
+
+    test_match.give(first, "scarecrow").await.unwrap();
+    let scarecrow = test_match.summon(first, "scarecrow", 0).await.unwrap();
+    test_match.summon(first, "rock_golem", 1).await.unwrap();
+    test_match.summon(first, "rock_golem", 2).await.unwrap();
+    test_match.pass_turn(first).await.unwrap();
+    let attacker = test_match.summon_hasted(second, "rock_golem", 0).await.unwrap();
+    test_match.attack(second, attacker, scarecrow).await.unwrap();
+    test_match
+}
+
+/// Everything both players received, as it would go over the wire.
+async fn wire_log(test_match: &TestMatch) -> Vec<String> {
+    let mut log = Vec::new();
+    for (recipient, message) in test_match.output.messages() {
+        log.push(match message {
+            OutgoingMessage::Instruction(instruction) => instruction.build(recipient).await.unwrap(),
+            message => format!("{:?}", message),
+        });
+    }
+    log
+}
+
+#[tokio::test]
+async fn matches_with_the_same_seed_replay_exactly() {
+    let test_match = play_seeded_match(19).await;
+    let replayed = play_seeded_match(19).await;
+
+    assert_eq!(wire_log(&test_match).await, wire_log(&replayed).await);
+    assert_eq!(test_match.game.resources.rng.seed, 19);
+}
+
+#[tokio::test]
+async fn matches_with_different_seeds_differ() {
+    let test_match = play_seeded_match(19).await;
+    let other = play_seeded_match(20).await;
+
+    assert!(wire_log(&test_match).await != wire_log(&other).await);
+}
+
+#[tokio::test]
+async fn type_names_are_sent_on_start() {
+    let test_match = TestMatch::start(11, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+
+    for player_id in [PlayerId::Player1, PlayerId::Player2] {
+        assert!(test_match.was_sent(player_id, |instruction| matches!(instruction, InstructionToClient::TypeNames { types } if types.contains(&("specter".to_string(), "Specter".to_string())))));
+    }
+}
+
+#[tokio::test]
+async fn summoning_spends_thaum_and_reveals_the_unit() {
+    let mut test_match = TestMatch::start(2, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+
+    let golem = test_match.summon(first, "rock_golem", 0).await.unwrap();
+
+    assert_eq!(test_match.token(golem).location, location_ids::player_field_location_id(first, 0));
+    assert_eq!(test_match.thaum(first), 6);
+    assert_eq!(test_match.token(golem).equipment_slots.len(), 1);
+    assert!(test_match.was_sent(first.opponent(), |instruction| matches!(instruction, InstructionToClient::Reveal { token } if *token == golem)));
+}
+
+#[tokio::test]
+async fn summoning_out_of_turn_is_refused() {
+    let mut test_match = TestMatch::start(3, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let second = test_match.current_turn().opponent();
+    let golem = test_match.find_in_hand(second, "rock_golem").unwrap();
+
+    test_match.move_token(second, golem, location_ids::player_field_location_id(second, 0)).await.unwrap();
+
+    assert_eq!(test_match.token(golem).location, test_match.game.resources.get_player(second).hand);
+}
+
+#[tokio::test]
+async fn attacking_the_hero_deals_damage() {
+    let mut test_match = TestMatch::start(4, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let hero = test_match.hero(first.opponent());
+
+    let golem = test_match.summon_hasted(first, "rock_golem", 0).await.unwrap();
+    test_match.attack(first, golem, hero).await.unwrap();
+
+    assert_eq!(test_match.token(hero).current_stats.health, 25);
+    assert!(test_match.was_sent(first, |instruction| matches!(instruction, InstructionToClient::Animate { token, preset: AnimationPreset::Attack, .. } if *token == golem)));
+}
+
+#[tokio::test]
+async fn summoned_units_wait_a_turn_before_attacking() {
+    let mut test_match = TestMatch::start(17, &LARGE_GOLEM_SET, &LARGE_GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let second = first.opponent();
+
+    let golem = test_match.summon(first, "rock_golem", 0).await.unwrap();
+    assert!(test_match.open_prompts(first).contains_key(&PromptType::SelectToken(golem)) == false);
+    assert!(test_match.attack(first, golem, test_match.hero(second)).await.is_err());
+
+    test_match.pass_turn(first).await.unwrap();
+    test_match.pass_turn(second).await.unwrap();
+
+    assert_eq!(test_match.token(golem).counters.get("generic.attack_cooldown"), Some(&0));
+    assert!(test_match.open_prompts(first).contains_key(&PromptType::SelectToken(golem)));
+}
+
+#[tokio::test]
+async fn units_attack_once_per_turn() {
+    let mut test_match = TestMatch::start(18, &LARGE_GOLEM_SET, &LARGE_GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let second = first.opponent();
+    let hero = test_match.hero(second);
+
+    let golem = test_match.summon_hasted(first, "rock_golem", 0).await.unwrap();
+    test_match.attack(first, golem, hero).await.unwrap();
+
+    assert_eq!(test_match.token(golem).counters.get("generic.attacks_left"), Some(&0));
+    assert!(test_match.open_prompts(first).contains_key(&PromptType::SelectToken(golem)) == false);
+    assert!(test_match.attack(first, golem, hero).await.is_err());
+    assert_eq!(test_match.token(hero).current_stats.health, 25);
+
+    // The budget comes back at the start of the owner's next turn
+    test_match.pass_turn(first).await.unwrap();
+    test_match.pass_turn(second).await.unwrap();
+    assert_eq!(test_match.token(golem).counters.get("generic.attacks_left"), Some(&1));
+    assert!(test_match.open_prompts(first).contains_key(&PromptType::SelectToken(golem)));
+}
+
+#[tokio::test]
+async fn defeated_units_go_to_the_graveyard() {
+    let mut test_match = TestMatch::start(5, &GOLEM_SET, &FLAME_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let second = first.opponent();
+    let (first_unit, second_unit) = match first == PlayerId::Player1 {
+        true => ("rock_golem", "flame_golem"),
+        false => ("flame_golem", "rock_golem"),
+    };
+
+    let defender = test_match.summon(first, first_unit, 0).await.unwrap();
+    test_match.pass_turn(first).await.unwrap();
+    let attacker = test_match.summon_hasted(second, second_unit, 0).await.unwrap();
+    test_match.attack(second, attacker, defender).await.unwrap();
+
+    let graveyard = test_match.game.resources.board.get_side(first).graveyard;
+    let defender_instance = test_match.token(defender);
+    // Either golem falls to a single hit from the other one
+    assert_eq!(defender_instance.current_stats.health, 0);
+    assert_eq!(defender_instance.location, graveyard);
+    assert!(test_match.tokens_in(location_ids::player_field_location_id(first, 0)).is_empty());
+}
+
+#[tokio::test]
+async fn items_are_equipped_to_units() {
+    let mut test_match = TestMatch::start(6, &EQUIP_SET, &EQUIP_SET).await.unwrap();
+    let first = test_match.current_turn();
+
+    let golem = test_match.summon(first, "rock_golem", 0).await.unwrap();
+    let slot = test_match.token(golem).equipment_slots[0];
+    let item = test_match.find_in_hand(first, "bladesong_symphony").unwrap();
+    test_match.move_token(first, item, slot).await.unwrap();
+
+    assert_eq!(test_match.tokens_in(slot), vec![item]);
+    assert_eq!(test_match.token(item).location, slot);
+    assert_eq!(test_match.game.resources.equipment_slot_owners.get(&slot), Some(&golem));
+}
+
+#[tokio::test]
+async fn drawing_from_an_empty_set_loses_the_game() {
+    let short_set = ["specter_overlord", "farmland", "rock_golem", "rock_golem"];
+    let mut test_match = TestMatch::new(7);
+    let result = test_match.send(PlayerId::Player1, "start_game", &format!("/set1/{}/!set1//set2/{}/!set2/", GOLEM_SET.join(","), short_set.join(","))).await;
+
+    assert!(result.is_ok());
+    assert!(test_match.was_sent(PlayerId::Player1, |instruction| matches!(instruction, InstructionToClient::EndGame { winner: PlayerId::Player1, reason: GameOverReason::DeckOut, .. })));
+    assert_eq!(test_match.game.resources.game_over.as_ref().map(|game_over| game_over.winner), Some(PlayerId::Player1));
+}
+
+#[tokio::test]
+async fn destroying_a_hero_ends_the_game() {
+    let mut test_match = TestMatch::start(8, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let hero = test_match.hero(first.opponent());
+    test_match.game.resources.token_instances.get_mut(&hero).unwrap().current_stats.health = 5;
+
+    let golem = test_match.summon_hasted(first, "rock_golem", 0).await.unwrap();
+    let result = test_match.attack(first, golem, hero).await;
+
+    assert!(result.is_ok());
+    assert_eq!(test_match.token(hero).current_stats.health, 0);
+    assert!(test_match.was_sent(first.opponent(), |instruction| matches!(instruction, InstructionToClient::EndGame { winner, reason: GameOverReason::HeroDefeated, .. } if *winner == first)));
+}
+
+#[tokio::test]
+async fn attacks_on_adjacent_units_are_redirected() {
+    let mut test_match = TestMatch::start(9, &LARGE_GOLEM_SET, &LARGE_GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let second = first.opponent();
+
+    test_match.give(first, "ironhide_hare").await.unwrap();
+    let hare = test_match.summon(first, "ironhide_hare", 0).await.unwrap();
+    let golem = test_match.summon(first, "rock_golem", 1).await.unwrap();
+    test_match.pass_turn(first).await.unwrap();
+    let attacker = test_match.summon_hasted(second, "rock_golem", 0).await.unwrap();
+    test_match.attack(second, attacker, golem).await.unwrap();
+
+    assert_eq!(test_match.token(golem).current_stats.defense, 5);
+    assert_eq!(test_match.token(hare).current_stats.defense, 0);
+    assert_eq!(test_match.token(hare).current_stats.health, 5);
+    // The hare hits back with its own attack of 0, the golem never fought
+    assert_eq!(test_match.token(attacker).current_stats.defense, 5);
+    let hare_location = test_match.token(hare).location;
+    assert!(test_match.was_sent(second, |instruction| matches!(instruction, InstructionToClient::Animate { token, location, preset: AnimationPreset::Attack, .. } if *token == attacker && *location == hare_location)));
+}
+
+#[tokio::test]
+async fn scarecrows_redirect_attacks_to_another_unit() {
+    let mut test_match = TestMatch::start(15, &LARGE_GOLEM_SET, &LARGE_GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let second = first.opponent();
+
+    test_match.give(first, "scarecrow").await.unwrap();
+    let scarecrow = test_match.summon(first, "scarecrow", 0).await.unwrap();
+    let golem = test_match.summon(first, "rock_golem", 1).await.unwrap();
+    test_match.pass_turn(first).await.unwrap();
+    let attacker = test_match.summon_hasted(second, "rock_golem", 0).await.unwrap();
+    test_match.attack(second, attacker, scarecrow).await.unwrap();
+
+    // Neither the scarecrow, the attacker itself nor a hero can take the hit, which leaves the golem
+    assert_eq!(test_match.token(scarecrow).current_stats.health, 4);
+    assert_eq!(test_match.token(golem).current_stats.defense, 0);
+    assert_eq!(test_match.token(attacker).current_stats.defense, 0);
+    for player_id in [first, second] {
+        let hero = test_match.token(test_match.hero(player_id));
+        assert_eq!(hero.current_stats.health, hero.base_stats.health);
+    }
+}
+
+#[tokio::test]
+async fn attacks_are_not_redirected_to_protected_units() {
+    let mut test_match = TestMatch::start(15, &LARGE_GOLEM_SET, &LARGE_GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let second = first.opponent();
+
+    test_match.give(first, "scarecrow").await.unwrap();
+    let scarecrow = test_match.summon(first, "scarecrow", 0).await.unwrap();
+    let golem = test_match.find_in_hand(first, "rock_golem").unwrap();
+    test_match.run_action(golem, r#"
+        then = "add_behavior"
+        with = { target = "this", behavior = "generic.protected" }
+    "#).await.unwrap();
+    test_match.summon(first, "rock_golem", 1).await.unwrap();
+    test_match.pass_turn(first).await.unwrap();
+    let attacker = test_match.summon_hasted(second, "rock_golem", 0).await.unwrap();
+    test_match.attack(second, attacker, scarecrow).await.unwrap();
+
+    // The golem is the only unit the scarecrow can pass the attack to, and it can't be targeted
+    assert_eq!(test_match.token(scarecrow).current_stats.health, 4);
+    assert_eq!(test_match.token(golem).current_stats.defense, 5);
+    assert_eq!(test_match.token(attacker).current_stats.defense, 5);
+    assert!(test_match.was_sent(second, |instruction| matches!(instruction, InstructionToClient::Cancelled { .. })));
+}
+
+#[tokio::test]
+async fn unit_targets_still_find_tokens_outside_the_field() {
+    let mut test_match = TestMatch::start(19, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let in_hand = test_match.find_in_hand(first, "rock_golem").unwrap();
+
+    let cost = test_match.token(in_hand).cost;
+
+    test_match.run_action(test_match.hero(first), r#"
+        then = "modify_cost"
+        with = { target = { find = { filter = { owned_by = "owner", id_is = ["rock_golem"] } } }, amount = -1 }
+    "#).await.unwrap();
+
+    assert_eq!(test_match.token(in_hand).cost, cost - 1);
+}
+
+#[tokio::test]
+async fn attacks_cannot_be_redirected_to_the_attacker() {
+    let mut test_match = TestMatch::start(16, &LARGE_GOLEM_SET, &LARGE_GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let second = first.opponent();
+
+    let defender = test_match.summon(first, "rock_golem", 0).await.unwrap();
+    test_match.add_behavior(defender, r#"
+        [[trigger]]
+        when = "this:will_be_attacked"
+
+        [[action]]
+        then = "redirect_target"
+        with = { new_target = { context = { key = "attacker" } } }
+    "#).unwrap();
+    test_match.pass_turn(first).await.unwrap();
+    let attacker = test_match.summon_hasted(second, "rock_golem", 0).await.unwrap();
+    test_match.attack(second, attacker, defender).await.unwrap();
+
+    assert_eq!(test_match.token(defender).current_stats.defense, 0);
+    assert_eq!(test_match.token(attacker).current_stats.defense, 0);
+}
+
+#[tokio::test]
+async fn items_can_give_all_types() {
+    let mut test_match = TestMatch::start(10, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+
+    test_match.give(first, "a_wizards_hat").await.unwrap();
+    let golem = test_match.summon(first, "rock_golem", 0).await.unwrap();
+    let slot = test_match.token(golem).equipment_slots[0];
+    let hat = test_match.find_in_hand(first, "a_wizards_hat").unwrap();
+    test_match.move_token(first, hat, slot).await.unwrap();
+
+    let all_types = TOKEN_REGISTRY.lock().await.all_types();
+    assert!(all_types.iter().all(|token_type| test_match.token(golem).token_types.contains(token_type)));
+    assert!(test_match.was_sent(first.opponent(), |instruction| matches!(instruction, InstructionToClient::UpdateData { token_data } if token_data.instance_id == golem && token_data.token_types.len() == all_types.len())));
+}
+
+#[tokio::test]
+async fn turn_started_behaviors_fire_when_the_turn_passes() {
+    let mut test_match = TestMatch::start(12, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    test_match.give(first, "gatekeeper_of_the_void").await.unwrap();
+    let gatekeeper = test_match.summon(first, "gatekeeper_of_the_void", 0).await.unwrap();
+    assert_eq!(test_match.token(gatekeeper).current_stats.attack, 5);
+
+    test_match.pass_turn(first).await.unwrap();
+
+    // Warlord sums the attack of adjacent specters, and there are none
+    assert_eq!(test_match.current_turn(), first.opponent());
+    assert_eq!(test_match.token(gatekeeper).current_stats.attack, 0);
+    assert!(test_match.was_sent(first, |instruction| matches!(instruction, InstructionToClient::PassTurn { player_id } if *player_id == first.opponent())));
+}
+
+#[tokio::test]
+async fn commands_are_cast_on_a_chosen_target() {
+    let mut test_match = TestMatch::start(13, &LARGE_GOLEM_SET, &LARGE_GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let second = first.opponent();
+    test_match.pass_turn(first).await.unwrap();
+    let golem = test_match.summon(second, "rock_golem", 0).await.unwrap();
+    test_match.pass_turn(second).await.unwrap();
+
+    let fireball = test_match.give_fixture(first, "cast-command", FIREBALL).await.unwrap();
+    let thaum = test_match.thaum(first);
+    test_match.move_token(first, fireball, location_ids::player_field_location_id(first, 0)).await.unwrap();
+    assert!(test_match.open_prompts(first).contains_key(&PromptType::SelectToken(golem)));
+
+    test_match.callback(first, PromptType::SelectToken(golem)).await.unwrap();
+
+    assert_eq!(test_match.token(golem).current_stats.defense, 2);
+    assert_eq!(test_match.token(fireball).location, test_match.game.resources.board.get_side(first).graveyard);
+    assert_eq!(test_match.thaum(first), thaum - 2);
+}
+
+#[tokio::test]
+async fn commands_without_targets_are_refused() {
+    let mut test_match = TestMatch::start(14, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let fireball = test_match.give_fixture(first, "refused-command", FIREBALL).await.unwrap();
+
+    test_match.move_token(first, fireball, location_ids::player_field_location_id(first, 0)).await.unwrap();
+
+    assert_eq!(test_match.token(fireball).location, test_match.game.resources.get_player(first).hand);
+    assert!(test_match.was_sent(first, |instruction| matches!(instruction, InstructionToClient::Refused { reason } if reason == "Command has no valid targets")));
+}
+
+#[tokio::test]
+async fn actions_are_refused_while_a_prompt_has_to_be_answered() {
+    let mut test_match = TestMatch::start(13, &LARGE_GOLEM_SET, &LARGE_GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let second = first.opponent();
+    test_match.pass_turn(first).await.unwrap();
+    let golem = test_match.summon(second, "rock_golem", 0).await.unwrap();
+    test_match.pass_turn(second).await.unwrap();
+    let fireball = test_match.give_fixture(first, "prompted-command", FIREBALL).await.unwrap();
+    test_match.move_token(first, fireball, location_ids::player_field_location_id(first, 0)).await.unwrap();
+    let in_hand = test_match.find_in_hand(first, "rock_golem").unwrap();
+    let hand = test_match.game.resources.get_player(first).hand;
+    let sent_to_second = test_match.output.instructions_for(second).len();
+
+    test_match.pass_turn(first).await.unwrap();
+    test_match.move_token(first, in_hand, location_ids::player_field_location_id(first, 1)).await.unwrap();
+
+    assert_eq!(test_match.current_turn(), first);
+    assert_eq!(test_match.token(in_hand).location, hand);
+    assert!(test_match.was_sent(first, |instruction| matches!(instruction, InstructionToClient::Refused { reason } if reason == "Answer the open prompt first")));
+    assert!(test_match.was_sent(first, |instruction| matches!(instruction, InstructionToClient::MoveToken { token, to } if *token == in_hand && *to == hand)));
+    assert_eq!(test_match.output.instructions_for(second).len(), sent_to_second);
+
+    // The prompt is still open and can be answered
+    test_match.callback(first, PromptType::SelectToken(golem)).await.unwrap();
+    assert_eq!(test_match.token(golem).current_stats.defense, 2);
+}
+
+#[tokio::test]
+async fn invalid_moves_are_rolled_back_without_ending_the_game() {
+    let mut test_match = TestMatch::start(28, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let golem = test_match.find_in_hand(first, "rock_golem").unwrap();
+    let hand = test_match.game.resources.get_player(first).hand;
+    test_match.output.clear();
+
+    test_match.move_token(first, golem, LocationId(987654)).await.unwrap();
+    let hero = test_match.hero(first);
+    test_match.move_token(first, hero, location_ids::player_field_location_id(first, 0)).await.unwrap();
+
+    assert_eq!(test_match.token(golem).location, hand);
+    assert!(test_match.was_sent(first, |instruction| matches!(instruction, InstructionToClient::MoveToken { token, to } if *token == golem && *to == hand)));
+    assert!(test_match.was_sent(first, |instruction| matches!(instruction, InstructionToClient::Refused { reason } if reason == "This token can't be played")));
+    assert!(test_match.output.instructions_for(first.opponent()).is_empty());
+    test_match.summon(first, "rock_golem", 0).await.unwrap();
+}
@@ -0,0 +1,10 @@
+mod harness;
+mod matches;
+mod behaviors;
+mod lint;
+mod replays;
+mod snapshots;
+mod sessions;
+mod protocol;
+mod batches;
+mod game_over;
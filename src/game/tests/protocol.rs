@@ -0,0 +1,122 @@
+use serde_json::Value;
+
+use crate::game::id_types::{LocationId, PlayerId, TokenInstanceId};
+use crate::game::instruction::InstructionToClient;
+use crate::game::output_sink::OutgoingMessage;
+use crate::game::protocol::{InstructionFromClient, WireFormat};
+use crate::game::tests::harness::{TestMatch, GOLEM_SET};
+
+#[test]
+fn clients_opt_into_json_during_the_handshake() {
+    assert_eq!(WireFormat::negotiate(Some("chat, landmark.json.v1")), WireFormat::Json);
+    assert_eq!(WireFormat::negotiate(Some("landmark.json.v2")), WireFormat::Legacy);
+    assert_eq!(WireFormat::negotiate(None), WireFormat::Legacy);
+}
+
+#[test]
+fn both_formats_parse_to_the_same_command() {
+    let legacy = InstructionFromClient::parse(WireFormat::Legacy, "move_token|/token/5/!token//location/1001/!location/").unwrap();
+    let json = InstructionFromClient::parse(WireFormat::Json, r#"{"type":"move_token","token":5,"location":1001}"#).unwrap();
+    assert_eq!(legacy, InstructionFromClient::MoveToken { token: TokenInstanceId(5), location: LocationId(1001) });
+    assert_eq!(json, legacy);
+
+    let start = InstructionFromClient::parse(WireFormat::Json, r#"{"type":"start_game","set_1":["a","b"],"set_2":["c"]}"#).unwrap();
+    assert_eq!(start, InstructionFromClient::StartGame { set_1: vec!["a".to_string(), "b".to_string()], set_2: vec!["c".to_string()], seed: None });
+    assert!(InstructionFromClient::parse(WireFormat::Json, r#"{"type":"summon"}"#).is_err());
+}
+
+#[tokio::test]
+async fn json_keeps_separators_in_text_intact() {
+    let mut test_match = TestMatch::start(23, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let golem = test_match.find_in_hand(first, "rock_golem").unwrap();
+    test_match.add_behavior(golem, r#"
+        name = "Tricky;;name"
+        description = "Breaks /!the/ old;;format"
+        [[trigger]]
+        when = "this:has_been_summoned"
+
+        [[action]]
+        then = "cancel"
+    "#).unwrap();
+
+    let message = OutgoingMessage::Instruction(InstructionToClient::UpdateBehaviors { token_data: test_match.token(golem).clone() });
+    let json: Value = serde_json::from_str(&message.build_json(first).await.unwrap()).unwrap();
+    let behavior = json["behaviors"].as_array().unwrap().iter().find(|behavior| behavior["name"] == "Tricky;;name").unwrap();
+    assert_eq!(behavior["description"], "Breaks /!the/ old;;format");
+}
+
+#[tokio::test]
+async fn json_hides_tokens_like_the_legacy_format() {
+    let test_match = TestMatch::start(24, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let golem = test_match.find_in_hand(first, "rock_golem").unwrap();
+    let message = OutgoingMessage::Instruction(InstructionToClient::UpdateData { token_data: test_match.token(golem).clone() });
+
+    let owner: Value = serde_json::from_str(&message.build_json(first).await.unwrap()).unwrap();
+    let opponent: Value = serde_json::from_str(&message.build_json(first.opponent()).await.unwrap()).unwrap();
+    assert_eq!(owner["data"]["id"], "rock_golem");
+    assert_eq!(opponent["data"]["hidden"], true);
+    assert!(opponent["data"].get("id").is_none());
+}
+
+#[tokio::test]
+async fn json_seats_can_play_a_match() {
+    let mut test_match = TestMatch::new(25);
+    test_match.game.communicator.set_format(PlayerId::Player1, WireFormat::Json);
+    let start = serde_json::to_string(&InstructionFromClient::StartGame {
+        set_1: GOLEM_SET.iter().map(|id| id.to_string()).collect(),
+        set_2: GOLEM_SET.iter().map(|id| id.to_string()).collect(),
+        seed: Some(25),
+    }).unwrap();
+    test_match.game.handle_message(PlayerId::Player1, &start).await.unwrap();
+
+    assert_eq!(test_match.game.resources.rng.seed, 25);
+    assert_eq!(test_match.game.resources.board.side_1.field.len(), 8);
+}
+
+#[test]
+fn legacy_payloads_may_contain_separators() {
+    let command = InstructionFromClient::parse(WireFormat::Legacy, "start_game|/set1/a|b,c/!set1//set2/d/!set2/").unwrap();
+    assert_eq!(command, InstructionFromClient::StartGame { set_1: vec!["a|b".to_string(), "c".to_string()], set_2: vec!["d".to_string()], seed: None });
+    assert_eq!(InstructionFromClient::parse(WireFormat::Legacy, "pass_turn").unwrap(), InstructionFromClient::PassTurn);
+}
+
+#[test]
+fn invalid_commands_explain_what_is_wrong() {
+    let error = |format, message| InstructionFromClient::parse(format, message).unwrap_err().to_string();
+    assert_eq!(error(WireFormat::Legacy, "move_token|/token/5/!token/"), "move_token: missing /location/");
+    assert!(error(WireFormat::Legacy, "move_token|/token/five/!token//location/1/!location/").starts_with("move_token: invalid token \"five\""));
+    assert_eq!(error(WireFormat::Legacy, "summon|"), "Unknown instruction: summon");
+    assert_eq!(error(WireFormat::Legacy, "start_game|/set1/a,,b/!set1//set2/c/!set2/"), "start_game: set_1 contains an empty token id");
+    assert!(error(WireFormat::Json, r#"{"type":"move_token","token":1,"location":2,"player":1}"#).contains("unknown field"));
+    assert!(error(WireFormat::Json, r#"{"type":"callback","callback_id":1}"#).contains("missing field `value`"));
+}
+
+#[tokio::test]
+async fn invalid_commands_are_answered_with_an_error() {
+    let mut test_match = TestMatch::start(26, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    test_match.output.clear();
+
+    test_match.send(first, "move_token", "/token/nope/!token/").await.unwrap();
+    let messages = test_match.output.messages();
+    assert!(matches!(&messages[..], [(player_id, OutgoingMessage::Error(error))] if *player_id == first && error.starts_with("move_token: invalid token")));
+}
+
+#[tokio::test]
+async fn ids_survive_json_clients_that_read_numbers_as_doubles() {
+    let mut test_match = TestMatch::start(35, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let golem = test_match.summon_hasted(first, "rock_golem", 0).await.unwrap();
+
+    for token in test_match.game.resources.token_instances.keys() {
+        assert_eq!(token.0 as f64 as u64, token.0);
+    }
+    for prompt in test_match.open_prompts(first).values() {
+        assert_eq!(prompt.0 as f64 as u64, prompt.0);
+    }
+    let message = OutgoingMessage::Instruction(InstructionToClient::UpdateData { token_data: test_match.token(golem).clone() });
+    let json: Value = serde_json::from_str(&message.build_json(first).await.unwrap()).unwrap();
+    assert_eq!(json["token"].as_f64().map(|id| id as u64), Some(golem.0));
+}
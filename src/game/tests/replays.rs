@@ -0,0 +1,97 @@
+use std::fs;
+
+use crate::game::id_types::PlayerId;
+use crate::game::output_sink::OutgoingMessage;
+use crate::game::protocol::InstructionFromClient;
+use crate::game::replay::{Replay, ReplayEvent, ReplayRecorder, ReplayResult};
+use crate::game::tests::harness::{TestMatch, LARGE_GOLEM_SET};
+
+/// Plays a short unseeded match while recording it, and loads the recording back.
+async fn record_match(name: &str) -> (TestMatch, Replay) {
+    let path = std::env::temp_dir().join(format!("landmark-{}-{}.jsonl", name, std::process::id()));
+    let mut test_match = TestMatch::new(0);
+    test_match.game.communicator.record_to(ReplayRecorder::create(&path).unwrap());
+
+    let sets = format!("/set1/{}/!set1//set2/{}/!set2/", LARGE_GOLEM_SET.join(","), LARGE_GOLEM_SET.join(","));
+    test_match.send(PlayerId::Player1, "start_game", &sets).await.unwrap();
+    let first = test_match.current_turn();
+    let second = first.opponent();
+    let attacker = test_match.summon(first, "rock_golem", 0).await.unwrap();
+    test_match.pass_turn(first).await.unwrap();
+    let defender = test_match.summon(second, "rock_golem", 0).await.unwrap();
+    test_match.pass_turn(second).await.unwrap();
+    test_match.attack(first, attacker, defender).await.unwrap();
+
+    let replay = Replay::from_file(path.to_str().unwrap()).unwrap();
+    fs::remove_file(&path).unwrap();
+    (test_match, replay)
+}
+
+#[tokio::test]
+async fn recorded_matches_replay_exactly() {
+    let (test_match, replay) = record_match("replay").await;
+
+    let seed = test_match.game.resources.rng.seed;
+    assert!(replay.events.contains(&ReplayEvent::Seed { seed }));
+    assert!(replay.events.iter().any(|event| matches!(event, ReplayEvent::Received { player_id: PlayerId::Player1, command: InstructionFromClient::StartGame { seed: None, .. } })));
+    assert_eq!(replay.verify().await.unwrap(), ReplayResult::Matches { messages: test_match.output.messages().len() });
+}
+
+#[tokio::test]
+async fn replays_report_where_they_diverge() {
+    let (_, mut replay) = record_match("replay-diverged").await;
+    let (index, event) = replay.events.iter_mut()
+        .filter(|event| matches!(event, ReplayEvent::Sent { .. }))
+        .enumerate()
+        .nth(20)
+        .unwrap();
+    let ReplayEvent::Sent { message, .. } = event else { unreachable!() };
+    let original = message.clone();
+    *message = "tampered".to_string();
+
+    assert_eq!(replay.verify().await.unwrap(), ReplayResult::Diverged {
+        index,
+        expected: Some("tampered".to_string()),
+        actual: Some(original),
+    });
+}
+
+#[tokio::test]
+async fn replays_follow_a_session_through_a_rematch() {
+    let path = std::env::temp_dir().join(format!("landmark-replay-rematch-{}.jsonl", std::process::id()));
+    let mut test_match = TestMatch::new(0);
+    test_match.game.communicator.record_to(ReplayRecorder::create(&path).unwrap());
+
+    let sets = format!("/set1/{}/!set1//set2/{}/!set2/", LARGE_GOLEM_SET.join(","), LARGE_GOLEM_SET.join(","));
+    test_match.send(PlayerId::Player1, "start_game", &sets).await.unwrap();
+    test_match.send(test_match.current_turn(), "concede", "").await.unwrap();
+    test_match.send(PlayerId::Player1, "rematch", "").await.unwrap();
+    test_match.send(PlayerId::Player2, "rematch", "").await.unwrap();
+    let first = test_match.current_turn();
+    test_match.summon(first, "rock_golem", 0).await.unwrap();
+    test_match.pass_turn(first).await.unwrap();
+
+    let replay = Replay::from_file(path.to_str().unwrap()).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(replay.events.iter().filter(|event| matches!(event, ReplayEvent::Seed { .. })).count(), 2);
+    assert_eq!(replay.verify().await.unwrap(), ReplayResult::Matches { messages: test_match.output.messages().len() });
+}
+
+#[tokio::test]
+async fn messages_that_never_reach_the_game_are_left_out_of_replays() {
+    let path = std::env::temp_dir().join(format!("landmark-replay-malformed-{}.jsonl", std::process::id()));
+    let mut test_match = TestMatch::new(0);
+    test_match.game.communicator.record_to(ReplayRecorder::create(&path).unwrap());
+
+    let sets = format!("/set1/{}/!set1//set2/{}/!set2/", LARGE_GOLEM_SET.join(","), LARGE_GOLEM_SET.join(","));
+    test_match.send(PlayerId::Player1, "start_game", &sets).await.unwrap();
+    let first = test_match.current_turn();
+    test_match.send(first.opponent(), "move_token", "/token/five/!token/").await.unwrap();
+    test_match.pass_turn(first).await.unwrap();
+
+    let replay = Replay::from_file(path.to_str().unwrap()).unwrap();
+    fs::remove_file(&path).unwrap();
+    let errors = test_match.output.messages().iter().filter(|(recipient, message)| *recipient == first.opponent() && matches!(message, OutgoingMessage::Error(_))).count();
+    assert_eq!(errors, 1);
+    assert_eq!(replay.verify().await.unwrap(), ReplayResult::Matches { messages: test_match.output.messages().len() - errors });
+}
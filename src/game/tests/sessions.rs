@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::unbounded_channel;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::game::game_communicator::{ClientEvent, Connection, GameCommunicator};
+use crate::game::game_lobby;
+use crate::game::game_lobby::{GameLobby, LobbyJoin, RunningSession};
+use crate::game::game_service::run_game;
+use crate::game::id_types::PlayerId;
+use crate::game::instruction::InstructionToClient;
+use crate::game::protocol::WireFormat;
+use crate::game::snapshot;
+use crate::game::snapshot::GameSnapshot;
+use crate::game::tests::harness::{TestMatch, GOLEM_SET};
+
+#[tokio::test]
+async fn games_go_on_while_a_player_is_disconnected() {
+    let mut test_match = TestMatch::start(20, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let second = first.opponent();
+
+    test_match.game.communicator.push_event(ClientEvent::Disconnected(second));
+    assert!(matches!(test_match.game.communicator.next_event().await.unwrap(), ClientEvent::Disconnected(player_id) if player_id == second));
+    let sent_before = test_match.output.instructions_for(second).len();
+
+    test_match.pass_turn(first).await.unwrap();
+    assert_eq!(test_match.current_turn(), second);
+    assert_eq!(test_match.output.instructions_for(second).len(), sent_before);
+}
+
+#[tokio::test]
+async fn rejoining_players_get_the_whole_board_again() {
+    let mut test_match = TestMatch::start(21, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let second = first.opponent();
+
+    test_match.game.communicator.push_event(ClientEvent::Disconnected(second));
+    test_match.game.communicator.next_event().await.unwrap();
+    test_match.pass_turn(first).await.unwrap();
+
+    let sent_before = test_match.output.instructions_for(second).len();
+    test_match.game.communicator.attach_output(second, Box::new(test_match.output.clone()));
+    test_match.game.resend_state(second).await.unwrap();
+
+    let resync = test_match.output.instructions_for(second).split_off(sent_before);
+    assert!(matches!(resync.first(), Some(InstructionToClient::Resync)));
+    let hand = test_match.game.resources.get_player(second).hand;
+    for token in test_match.tokens_in(hand) {
+        assert!(resync.iter().any(|instruction| matches!(instruction, InstructionToClient::CreateToken { instance_id, .. } if *instance_id == token)));
+    }
+    assert!(resync.iter().any(|instruction| matches!(instruction, InstructionToClient::PassTurn { player_id } if *player_id == second)));
+}
+
+#[tokio::test]
+async fn games_end_once_both_players_are_gone() {
+    let mut test_match = TestMatch::start(22, &GOLEM_SET, &GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+
+    test_match.game.communicator.push_event(ClientEvent::Disconnected(first));
+    test_match.game.communicator.push_event(ClientEvent::Disconnected(first.opponent()));
+    assert!(test_match.game.communicator.next_event().await.is_ok());
+    assert!(test_match.game.communicator.next_event().await.is_err());
+}
+
+#[test]
+fn rejoin_tokens_are_read_from_the_query() {
+    assert_eq!(game_lobby::get_rejoin_token(Some("session=table&rejoin=00ff")), Some("00ff".to_string()));
+    assert_eq!(game_lobby::get_rejoin_token(Some("session=table")), None);
+    assert_eq!(game_lobby::get_session(Some("rejoin=00ff&session=table")), Some("table".to_string()));
+    assert_eq!(game_lobby::get_session(Some("session=")), None);
+    assert_eq!(game_lobby::get_session(None), None);
+}
+
+/// A websocket to a local client, handed over the way the lobby does it.
+async fn connect_client() -> (Connection, WebSocketStream<TcpStream>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let stream = TcpStream::connect(address).await.unwrap();
+        tokio_tungstenite::client_async(format!("ws://{}/game", address), stream).await.unwrap().0
+    });
+    let (stream, _) = listener.accept().await.unwrap();
+    let websocket = tokio_tungstenite::accept_async(stream).await.unwrap();
+    (Connection::new(websocket, WireFormat::Legacy), client.await.unwrap())
+}
+
+/// Reads from a client until a message starts with the given prefix.
+async fn read_until(client: &mut WebSocketStream<TcpStream>, prefix: &str) -> bool {
+    let read = async {
+        while let Some(Ok(message)) = client.next().await {
+            if message.to_text().map_or(false, |text| text.starts_with(prefix)) {
+                return true;
+            }
+        }
+        false
+    };
+    tokio::time::timeout(Duration::from_secs(5), read).await.unwrap_or(false)
+}
+
+#[tokio::test]
+async fn rejoined_seats_stay_connected_when_their_old_socket_closes() {
+    let session = format!("stale-socket-{}", std::process::id());
+    let game_id = "00000000005e55e0".to_string();
+    let rejoin_tokens = HashMap::from([
+        (snapshot::new_rejoin_token(&game_id), PlayerId::Player1),
+        (snapshot::new_rejoin_token(&game_id), PlayerId::Player2),
+    ]);
+    let (communicator, _) = GameCommunicator::headless();
+    let events = communicator.event_sender();
+    let game = tokio::spawn({
+        let (session, game_id) = (session.clone(), game_id.clone());
+        async move { run_game(&session, &game_id, communicator, &rejoin_tokens, None).await }
+    });
+
+    let (stale, mut stale_client) = connect_client().await;
+    assert!(events.send(ClientEvent::Rejoined(PlayerId::Player2, stale)).is_ok());
+    assert!(read_until(&mut stale_client, "resync|").await);
+    let (fresh, mut fresh_client) = connect_client().await;
+    assert!(events.send(ClientEvent::Rejoined(PlayerId::Player2, fresh)).is_ok());
+    assert!(read_until(&mut fresh_client, "resync|").await);
+
+    // The server only notices the dropped socket after the seat already came back
+    stale_client.close(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    fresh_client.send(Message::Text("not_an_instruction|".to_string())).await.unwrap();
+    assert!(read_until(&mut fresh_client, "error|").await);
+
+    game.abort();
+    let _ = fs::remove_file(GameSnapshot::path(&game_id));
+    for replay in fs::read_dir("replays").unwrap().flatten() {
+        if replay.file_name().to_string_lossy().starts_with(&session) {
+            fs::remove_file(replay.path()).unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn sessions_in_play_refuse_players_without_a_seat() {
+    let mut lobby = GameLobby::new();
+    let (first, _first_client) = connect_client().await;
+    let (second, _second_client) = connect_client().await;
+    assert!(matches!(lobby.join("table", None, first), LobbyJoin::Waiting));
+    assert!(matches!(lobby.join("table", None, second), LobbyJoin::Matched { .. }));
+
+    // Matched but not started yet
+    let (late, _late_client) = connect_client().await;
+    assert!(matches!(lobby.join("table", None, late), LobbyJoin::Refused(_)));
+
+    let (events, _incoming) = unbounded_channel();
+    lobby.start_session("table", RunningSession { seats: HashMap::new(), events });
+    let (late, _late_client) = connect_client().await;
+    assert!(matches!(lobby.join("table", Some("not-a-seat"), late), LobbyJoin::Refused(_)));
+
+    lobby.end_session("table");
+    let (next, _next_client) = connect_client().await;
+    assert!(matches!(lobby.join("table", None, next), LobbyJoin::Waiting));
+}
+
+#[tokio::test]
+async fn players_that_left_the_lobby_are_not_matched() {
+    let mut lobby = GameLobby::new();
+    let (gone, mut gone_client) = connect_client().await;
+    assert!(matches!(lobby.join("empty-table", None, gone), LobbyJoin::Waiting));
+    gone_client.close(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (second, _second_client) = connect_client().await;
+    assert!(matches!(lobby.join("empty-table", None, second), LobbyJoin::Waiting));
+}
+
+#[tokio::test]
+async fn messages_sent_while_waiting_reach_the_game() {
+    let mut lobby = GameLobby::new();
+    let (first, mut first_client) = connect_client().await;
+    assert!(matches!(lobby.join("eager-table", None, first), LobbyJoin::Waiting));
+    first_client.send(Message::Text("pass_turn|".to_string())).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (second, _second_client) = connect_client().await;
+    let LobbyJoin::Matched { player_1, .. } = lobby.join("eager-table", None, second) else { panic!("Players were not matched") };
+    assert_eq!(player_1.pending, vec!(Message::Text("pass_turn|".to_string())));
+}
@@ -0,0 +1,112 @@
+use crate::game::game_communicator::GameCommunicator;
+use crate::game::game_service::GameSession;
+use crate::game::id_types::TokenInstanceId;
+use crate::game::instruction::InstructionToClient;
+use crate::game::prompts::PromptType;
+use crate::game::id_types::PlayerId;
+use crate::game::snapshot;
+use crate::game::snapshot::GameSnapshot;
+use crate::game::tests::harness::{TestMatch, LARGE_GOLEM_SET};
+
+/// Plays until the first player has picked an attacker and still has to pick a target.
+async fn match_waiting_for_target() -> (TestMatch, TokenInstanceId, TokenInstanceId) {
+    let mut test_match = TestMatch::start(19, &LARGE_GOLEM_SET, &LARGE_GOLEM_SET).await.unwrap();
+    let first = test_match.current_turn();
+    let second = first.opponent();
+    let attacker = test_match.summon(first, "rock_golem", 0).await.unwrap();
+    test_match.pass_turn(first).await.unwrap();
+    let defender = test_match.summon(second, "rock_golem", 0).await.unwrap();
+    test_match.pass_turn(second).await.unwrap();
+    test_match.callback(first, PromptType::SelectToken(attacker)).await.unwrap();
+    (test_match, attacker, defender)
+}
+
+/// Serializes a game and loads it into a fresh session, the way a restarted server would.
+async fn restore(test_match: &TestMatch) -> TestMatch {
+    let data = serde_json::to_string(&test_match.game.snapshot()).unwrap();
+    let snapshot: GameSnapshot = serde_json::from_str(&data).unwrap();
+    let (communicator, output) = GameCommunicator::headless();
+    TestMatch {
+        game: GameSession::restore(snapshot, communicator).await.unwrap(),
+        output,
+        seed: test_match.seed,
+    }
+}
+
+async fn wire_log_since(test_match: &TestMatch, start: usize) -> Vec<String> {
+    let mut log = Vec::new();
+    for (recipient, message) in test_match.output.messages().into_iter().skip(start) {
+        log.push(message.build(recipient).await.unwrap());
+    }
+    log
+}
+
+#[tokio::test]
+async fn restored_games_continue_like_the_original() {
+    let (mut original, _, defender) = match_waiting_for_target().await;
+    let mut restored = restore(&original).await;
+    let first = original.current_turn();
+    assert_eq!(restored.current_turn(), first);
+
+    restored.game.resend_state(first).await.unwrap();
+    let original_start = original.output.messages().len();
+    let restored_start = restored.output.messages().len();
+
+    for test_match in [&mut original, &mut restored] {
+        test_match.callback(first, PromptType::AttackToken(defender)).await.unwrap();
+        test_match.pass_turn(first).await.unwrap();
+        test_match.give(first.opponent(), "scarecrow").await.unwrap();
+    }
+
+    assert_eq!(wire_log_since(&restored, restored_start).await, wire_log_since(&original, original_start).await);
+    assert_eq!(restored.token(defender).current_stats.defense, original.token(defender).current_stats.defense);
+}
+
+#[tokio::test]
+async fn resending_the_state_recreates_every_token() {
+    let (original, attacker, _) = match_waiting_for_target().await;
+    let mut restored = restore(&original).await;
+    let first = restored.current_turn();
+    let second = first.opponent();
+    restored.game.resend_state(second).await.unwrap();
+
+    for token in restored.game.resources.token_instances.keys() {
+        assert!(restored.was_sent(second, |instruction| matches!(instruction, InstructionToClient::CreateToken { instance_id, .. } if instance_id == token)));
+    }
+    assert!(restored.was_sent(second, |instruction| matches!(instruction, InstructionToClient::PassTurn { player_id } if *player_id == first)));
+    // The prompt belongs to the player whose turn it is, only they get it again
+    assert!(restored.open_prompts(second).is_empty());
+    assert!(restored.output.instructions_for(first).is_empty());
+    assert_eq!(restored.game.resources.token_instances.len(), original.game.resources.token_instances.len());
+    assert_eq!(restored.token(attacker).location, original.token(attacker).location);
+}
+
+#[test]
+fn rejoin_tokens_name_their_game() {
+    let token = snapshot::new_rejoin_token("00ff");
+    assert_eq!(snapshot::game_id_of(&token), Some("00ff"));
+    assert_eq!(snapshot::game_id_of("../secret.00ff"), None);
+    assert_eq!(snapshot::game_id_of("00ff"), None);
+    assert_eq!(snapshot::game_id_of(".00ff"), None);
+}
+
+#[tokio::test]
+async fn saved_games_are_only_resumed_for_their_own_seats() {
+    let (test_match, _, _) = match_waiting_for_target().await;
+    let game_id = format!("{:x}", std::process::id());
+    let (token_1, token_2) = (snapshot::new_rejoin_token(&game_id), snapshot::new_rejoin_token(&game_id));
+    let mut saved = test_match.game.snapshot();
+    saved.seats = vec![(token_1.clone(), PlayerId::Player1), (token_2.clone(), PlayerId::Player2)];
+    let path = GameSnapshot::path(&game_id);
+    saved.save(&path).unwrap();
+
+    let stranger = snapshot::new_rejoin_token(&game_id);
+    assert!(GameSnapshot::find([None, None]).is_none());
+    assert!(GameSnapshot::find([Some(&token_1), None]).is_none());
+    assert!(GameSnapshot::find([Some(&token_1), Some(&stranger)]).is_none());
+    assert!(GameSnapshot::find([Some(&token_1), Some(&token_1)]).is_none());
+    let resumed = GameSnapshot::find([Some(&token_2), Some(&token_1)]);
+
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(resumed.and_then(|resumed| resumed.seat_of(&token_2)), Some(PlayerId::Player2));
+}
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+use std::fs;
+use color_eyre::eyre::{ContextCompat, eyre};
+
+use color_eyre::Result;
+use serde::Deserialize;
+
+use crate::game::tokens::token_deserializer::TokenBehavior;
+
+#[derive(Deserialize)]
+struct BehaviorFile {
+    #[serde(rename = "behavior", default)]
+    behaviors: Vec<TokenBehavior>,
+}
+
+/// Behaviors shared between tokens, referenced by their id.
+pub struct BehaviorRegistry {
+    pub behavior_registry: HashMap<String, TokenBehavior>,
+}
+
+impl BehaviorRegistry {
+    pub fn from_file(path: &str) -> Result<Self> {
+        eprintln!("Loading behaviors from {}", path);
+
+        let file: BehaviorFile = toml::from_str(&fs::read_to_string(path)?)?;
+        let mut registry = HashMap::new();
+        for behavior in file.behaviors {
+            let id = behavior.id.clone().context(format!("A behavior in {} has no id", path))?;
+            eprintln!("Loading behavior: {}", id);
+            if registry.insert(id.clone(), behavior).is_some() {
+                return Err(eyre!("Behavior {} is defined more than once", id));
+            }
+        }
+
+        Ok(BehaviorRegistry {
+            behavior_registry: registry
+        })
+    }
+
+    pub fn get_data(&self, id: &str) -> Result<&TokenBehavior> {
+        self.behavior_registry.get(id).context(eyre!("Behavior not found: {}", id))
+    }
+}
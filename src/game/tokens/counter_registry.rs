@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::fs;
+use color_eyre::eyre::{ContextCompat, eyre};
+
+use color_eyre::Result;
+use serde::Deserialize;
+
+/// Counters the server itself keeps on tokens, behaviors can change them like any other counter.
+pub mod counter_ids {
+    /// Turns a unit has to wait before it can attack, set when it is summoned.
+    pub const ATTACK_COOLDOWN: &str = "generic.attack_cooldown";
+    /// Attacks a unit has left this turn.
+    pub const ATTACKS_LEFT: &str = "generic.attacks_left";
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CounterData {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CounterFile {
+    #[serde(rename = "counter", default)]
+    counters: Vec<CounterData>,
+}
+
+pub struct CounterRegistry {
+    pub counter_registry: HashMap<String, CounterData>,
+}
+
+impl CounterRegistry {
+    pub fn from_file(path: &str) -> Result<Self> {
+        eprintln!("Loading counters from {}", path);
+
+        let file: CounterFile = toml::from_str(&fs::read_to_string(path)?)?;
+        let mut registry = HashMap::new();
+        for counter in file.counters {
+            eprintln!("Loading counter: {}", counter.id);
+            if let Some(existing) = registry.insert(counter.id.clone(), counter) {
+                return Err(eyre!("Counter {} is defined more than once", existing.id));
+            }
+        }
+
+        Ok(CounterRegistry {
+            counter_registry: registry
+        })
+    }
+
+    pub fn get_data(&self, id: &str) -> Result<&CounterData> {
+        self.counter_registry.get(id).context(eyre!("Counter not found: {}", id))
+    }
+}
@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+use std::fs;
+use color_eyre::eyre::{ContextCompat, eyre};
+
+use color_eyre::Result;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct KeywordData {
+    pub id: String,
+    pub text: String,
+}
+
+#[derive(Deserialize)]
+struct KeywordFile {
+    #[serde(rename = "keyword", default)]
+    keywords: Vec<KeywordData>,
+}
+
+pub struct KeywordRegistry {
+    pub keyword_registry: HashMap<String, KeywordData>,
+}
+
+impl KeywordRegistry {
+    pub fn from_file(path: &str) -> Result<Self> {
+        eprintln!("Loading keywords from {}", path);
+
+        let file: KeywordFile = toml::from_str(&fs::read_to_string(path)?)?;
+        let mut registry = HashMap::new();
+        for keyword in file.keywords {
+            if let Some(existing) = registry.insert(keyword.id.clone(), keyword) {
+                return Err(eyre!("Keyword {} is defined more than once", existing.id));
+            }
+        }
+
+        Ok(KeywordRegistry {
+            keyword_registry: registry
+        })
+    }
+
+    pub fn get_data(&self, id: &str) -> Result<&KeywordData> {
+        self.keyword_registry.get(id).context(eyre!("Keyword not found: {}", id))
+    }
+}
@@ -2,4 +2,10 @@ pub mod token_instance;
 //pub mod token_behavior;
 pub mod token_deserializer;
 pub mod token_registry;
+pub mod counter_registry;
+pub mod behavior_registry;
+pub mod keyword_registry;
+pub mod type_registry;
+pub mod text_template;
+pub mod token_linter;
 pub mod token_behaviors;
\ No newline at end of file
@@ -0,0 +1,98 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use color_eyre::eyre::eyre;
+
+use color_eyre::Result;
+
+use crate::game::tokens::keyword_registry::KeywordRegistry;
+use crate::game::tokens::token_deserializer::TokenBehavior;
+use crate::game::tokens::token_instance::UnitStats;
+use crate::game::tokens::type_registry::TypeRegistry;
+
+const STATS: [&str; 4] = ["cost", "health", "defense", "attack"];
+
+/// Expands `{{kind:id}}` placeholders in token texts.
+/// Keywords, types and token names are resolved once at load. Stats change during a game, so they are
+/// only checked at load and filled in whenever a token is sent. Tokens that don't exist yet are shown by
+/// their id, the linter reports them.
+pub struct TextTemplates<'a> {
+    pub keywords: &'a KeywordRegistry,
+    pub types: &'a TypeRegistry,
+    pub token_names: HashMap<String, String>,
+}
+
+impl TextTemplates<'_> {
+    pub fn expand(&self, text: &str) -> Result<String> {
+        expand_placeholders(text, |kind, id| Ok(match kind {
+            "keyword" => Some(self.keywords.get_data(id)?.text.clone()),
+            "type" => Some(self.types.get_data(id)?.name.clone()),
+            "token" => Some(self.token_names.get(id).cloned().unwrap_or(id.to_string())),
+            "stat" if STATS.contains(&id) => None,
+            "stat" => return Err(eyre!("Stat not found: {}", id)),
+            _ => return Err(eyre!("Unknown placeholder {{{{{}:{}}}}}", kind, id)),
+        }))
+    }
+
+    /// Tokens a text refers to that don't exist.
+    pub fn missing_tokens(&self, text: &str) -> Vec<String> {
+        let missing = RefCell::new(Vec::new());
+        let _ = expand_placeholders(text, |kind, id| {
+            if kind == "token" && self.token_names.contains_key(id) == false {
+                missing.borrow_mut().push(id.to_string());
+            }
+            Ok(None)
+        });
+        missing.into_inner()
+    }
+
+    fn behavior_texts(behavior: &TokenBehavior) -> impl Iterator<Item = &String> {
+        behavior.name.iter().chain(behavior.description.iter())
+    }
+
+    pub fn missing_behavior_tokens(&self, behavior: &TokenBehavior) -> Vec<String> {
+        Self::behavior_texts(behavior).flat_map(|text| self.missing_tokens(text)).collect()
+    }
+
+    pub fn expand_behavior(&self, behavior: &mut TokenBehavior) -> Result<()> {
+        if let Some(name) = &behavior.name {
+            behavior.name = Some(self.expand(name)?);
+        }
+        if let Some(description) = &behavior.description {
+            behavior.description = Some(self.expand(description)?);
+        }
+        Ok(())
+    }
+}
+
+/// Fills in the live stats of a token.
+pub fn expand_stats(text: &str, cost: u32, stats: &UnitStats) -> String {
+    let expanded = expand_placeholders(text, |kind, id| Ok(match (kind, id) {
+        ("stat", "cost") => Some(cost.to_string()),
+        ("stat", "health") => Some(stats.health.to_string()),
+        ("stat", "defense") => Some(stats.defense.to_string()),
+        ("stat", "attack") => Some(stats.attack.to_string()),
+        _ => None,
+    }));
+    // Texts are checked at load, anything left over is sent as is
+    expanded.unwrap_or(text.to_string())
+}
+
+/// Replaces every placeholder the resolver returns a value for and keeps the others untouched.
+fn expand_placeholders(text: &str, resolve: impl Fn(&str, &str) -> Result<Option<String>>) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..].find("}}").ok_or(eyre!("Unclosed placeholder in \"{}\"", text))? + start;
+        let placeholder = &rest[start + 2..end];
+        let (kind, id) = placeholder.split_once(':').ok_or(eyre!("Placeholder {{{{{}}}}} has no kind", placeholder))?;
+
+        expanded.push_str(&rest[..start]);
+        match resolve(kind.trim(), id.trim())? {
+            Some(replacement) => expanded.push_str(&replacement),
+            None => expanded.push_str(&rest[start..end + 2]),
+        }
+        rest = &rest[end + 2..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
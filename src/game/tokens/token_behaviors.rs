@@ -9,7 +9,7 @@ use crate::game::game_communicator::GameCommunicator;
 use crate::game::id_types::{TokenInstanceId, PlayerId};
 use crate::game::new_state_machine::StateMachine;
 use crate::game::state_resources::StateResources;
-use crate::game::game_context::{context_keys, GameContext};
+use crate::game::game_context::{context_keys, ContextValue, GameContext};
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum TokenBehaviorResult {
@@ -20,6 +20,9 @@ pub enum TokenBehaviorResult {
 pub async fn trigger_token_behaviors(token_instance_id: TokenInstanceId, trigger_name: TokenBehaviorTriggerWhenName, context: &mut GameContext, state: &mut StateMachine, resources: &mut StateResources, communicator: &mut GameCommunicator) -> Result<()> {
     let token = resources.token_instances.get(&token_instance_id).context(format!("Tried to process behaviors for token that does not exist: {}", token_instance_id))?;
 
+    let location = token.location;
+    let token_name = token.token_data.name.clone();
+    let is_hidden = token.hidden;
     let is_owned = token.owner == context.get(context_keys::OWNER)?.as_player_id()?;
     let is_context_this = context.get(context_keys::TRIGGER_THIS).map_or(
         Ok::<bool, Error>(false),
@@ -47,6 +50,10 @@ pub async fn trigger_token_behaviors(token_instance_id: TokenInstanceId, trigger
                 continue;
             }
 
+            if trigger.is_live_in(location) == false {
+                continue;
+            }
+
             if let Some(and) = &trigger.and {
                 if and.check(context, resources, communicator).await? == false { continue; }
             }
@@ -56,8 +63,17 @@ pub async fn trigger_token_behaviors(token_instance_id: TokenInstanceId, trigger
 
         if successful_triggers.len() > 0 {
             for action in behavior.actions.iter().rev() {
-                let result= action.run(context, resources, state, communicator).await?;
-                // Todo: Do something with result
+                let result = action.run(context, resources, state, communicator).await?;
+                if result == TokenBehaviorResult::Cancel {
+                    // The next CheckCancel of the group ends it and tells the players why
+                    let reason = match (is_hidden, &behavior.name) {
+                        (true, _) => "Cancelled by a hidden token".to_string(),
+                        (false, Some(behavior_name)) => format!("Cancelled by {} ({})", token_name, behavior_name),
+                        (false, None) => format!("Cancelled by {}", token_name),
+                    };
+                    context.insert(context_keys::CANCEL, ContextValue::Bool(true));
+                    context.insert(context_keys::CANCEL_REASON, ContextValue::String(reason));
+                }
             }
         }
     }
@@ -2,25 +2,27 @@ use std::fmt;
 use color_eyre::eyre::{Context, ContextCompat};
 
 use color_eyre::Result;
-use serde::{de, Deserialize, Deserializer};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use serde::__private::de::EnumDeserializer;
 use serde::de::{Error, MapAccess, Unexpected, Visitor};
 use serde::de::value::StringDeserializer;
-use serde_enum_str::Deserialize_enum_str;
+use serde_enum_str::{Deserialize_enum_str, Serialize_enum_str};
 use crate::game::tokens;
 
 use crate::game::tokens::token_behaviors::TokenBehaviorResult;
 use crate::game::tokens::token_instance::TokenInstance;
 use crate::game::game_communicator::GameCommunicator;
-use crate::game::id_types::{TokenInstanceId, location_ids, PlayerId};
+use crate::game::id_types::{TokenInstanceId, location_ids, LocationId, PlayerId};
+use crate::game::id_types::location_ids::LocationIdentity;
 use crate::game::id_types::PlayerId::{Player1, Player2};
 use crate::game::instruction::InstructionToClient;
 use crate::game::new_state_machine::StateMachine;
 use crate::game::player::Player;
 use crate::game::state_resources::StateResources;
-use crate::game::game_context::{context_keys, GameContext};
+use crate::game::game_context::{context_keys, ContextValue, GameContext};
+use crate::TOKEN_REGISTRY;
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TokenData {
     #[serde(default)] pub nightly: bool,
     #[serde(skip_deserializing)] pub id: String,
@@ -34,9 +36,13 @@ pub struct TokenData {
 
     #[serde(rename = "behavior", default)]
     pub behaviors: Vec<TokenBehavior>,
+
+    /// Ids of behaviors from the behavior registry every instance of this token starts with.
+    #[serde(default)]
+    pub shared_behaviors: Vec<String>,
 }
 
-#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
 pub struct SlotPosition {
     pub x: i32,
     pub y: i32,
@@ -52,7 +58,7 @@ impl SlotPosition {
     }
 }
 
-#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 #[serde(rename_all = "snake_case", tag = "category")]
 pub enum TokenCategory {
     Hero {
@@ -66,13 +72,19 @@ pub enum TokenCategory {
         health: i32,
         #[serde(default)] attack: i32,
         #[serde(default)] defense: i32,
+        /// How many times the unit can attack each turn.
+        #[serde(default = "TokenCategory::one_attack")] attacks_per_turn: i32,
     },
     Item,
-    Command,
+    Command {
+        /// Which token the player has to pick when casting, available to the behaviors as `cast_target`.
+        #[serde(default)] cast_target: Option<TokenFilter>,
+    },
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TokenBehavior {
+    pub id: Option<String>,
     pub name: Option<String>,
     pub description: Option<String>,
 
@@ -83,19 +95,223 @@ pub struct TokenBehavior {
     pub actions: Vec<TokenBehaviorAction>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+impl TokenBehavior {
+    /// Every counter this behavior reads or writes.
+    pub fn get_counters(&self) -> Vec<&String> {
+        let mut counters = Vec::new();
+        for trigger in &self.triggers {
+            if let Some(TokenBehaviorTriggerAnd::Counter { counter, .. }) = &trigger.and {
+                counters.push(counter);
+            }
+        }
+        for action in &self.actions {
+            match action {
+                TokenBehaviorAction::SetCounter { counter, .. } => counters.push(counter),
+                TokenBehaviorAction::ModifyCounter { counter, .. } => counters.push(counter),
+                _ => {}
+            }
+        }
+        counters
+    }
+
+    /// Every token type this behavior checks for or hands out, including the ones used in filters.
+    pub fn get_types(&self) -> Vec<&String> {
+        let mut types = Vec::new();
+        for trigger in &self.triggers {
+            match &trigger.and {
+                Some(TokenBehaviorTriggerAnd::TypeContains { target, types: checked_types }) => {
+                    types.extend(checked_types);
+                    types.extend(target.get_types());
+                }
+                Some(TokenBehaviorTriggerAnd::Count { filter, .. }) => types.extend(filter.get_types()),
+                Some(TokenBehaviorTriggerAnd::AdjacentTo { source, target }) => {
+                    types.extend(source.get_types());
+                    types.extend(target.get_types());
+                }
+                Some(TokenBehaviorTriggerAnd::Counter { target, .. }) => types.extend(target.get_types()),
+                Some(TokenBehaviorTriggerAnd::SelectionIntention { .. }) | None => {}
+            }
+        }
+        for action in &self.actions {
+            match action {
+                TokenBehaviorAction::AddTypes { target, types: added_types } => {
+                    types.extend(added_types);
+                    types.extend(target.get_types());
+                }
+                TokenBehaviorAction::SelectUnit { filter, .. } => types.extend(filter.get_types()),
+                TokenBehaviorAction::SumAttack { target, filter } => {
+                    types.extend(target.get_types());
+                    types.extend(filter.get_types());
+                }
+                TokenBehaviorAction::Replace { target, .. }
+                | TokenBehaviorAction::ModifyAttack { target, .. }
+                | TokenBehaviorAction::ModifyHealth { target, .. }
+                | TokenBehaviorAction::ModifyDefense { target, .. }
+                | TokenBehaviorAction::ModifyCost { target, .. }
+                | TokenBehaviorAction::Summon { target, .. }
+                | TokenBehaviorAction::DamageUnit { target, .. } => types.extend(target.get_types()),
+                TokenBehaviorAction::RedirectTarget { new_target } => types.extend(new_target.get_types()),
+                TokenBehaviorAction::Destroy { target }
+                | TokenBehaviorAction::GiveAllTypes { target }
+                | TokenBehaviorAction::AddBehavior { target, .. }
+                | TokenBehaviorAction::RemoveBehavior { target, .. }
+                | TokenBehaviorAction::SetCounter { target, .. }
+                | TokenBehaviorAction::ModifyCounter { target, .. } => types.extend(target.get_types()),
+                TokenBehaviorAction::DrawToken { .. }
+                | TokenBehaviorAction::DamageHero { .. }
+                | TokenBehaviorAction::Cancel
+                | TokenBehaviorAction::SaveContext { .. }
+                | TokenBehaviorAction::CreateToken { .. } => {}
+            }
+        }
+        types
+    }
+
+    /// Every context key this behavior reads a token from.
+    pub fn get_context_keys(&self) -> Vec<&String> {
+        let mut keys = Vec::new();
+        for trigger in &self.triggers {
+            match &trigger.and {
+                Some(TokenBehaviorTriggerAnd::TypeContains { target, .. }) => keys.extend(target.get_context_keys()),
+                Some(TokenBehaviorTriggerAnd::Count { filter, .. }) => keys.extend(filter.get_context_keys()),
+                Some(TokenBehaviorTriggerAnd::AdjacentTo { source, target }) => {
+                    keys.extend(source.get_context_keys());
+                    keys.extend(target.get_context_keys());
+                }
+                Some(TokenBehaviorTriggerAnd::Counter { target, .. }) => keys.extend(target.get_context_keys()),
+                Some(TokenBehaviorTriggerAnd::SelectionIntention { .. }) | None => {}
+            }
+        }
+        for action in &self.actions {
+            match action {
+                TokenBehaviorAction::SelectUnit { filter, .. } => keys.extend(filter.get_context_keys()),
+                TokenBehaviorAction::SumAttack { target, filter } => {
+                    keys.extend(target.get_context_keys());
+                    keys.extend(filter.get_context_keys());
+                }
+                TokenBehaviorAction::Replace { target, .. }
+                | TokenBehaviorAction::AddTypes { target, .. }
+                | TokenBehaviorAction::ModifyAttack { target, .. }
+                | TokenBehaviorAction::ModifyHealth { target, .. }
+                | TokenBehaviorAction::ModifyDefense { target, .. }
+                | TokenBehaviorAction::ModifyCost { target, .. }
+                | TokenBehaviorAction::Summon { target, .. }
+                | TokenBehaviorAction::DamageUnit { target, .. } => keys.extend(target.get_context_keys()),
+                TokenBehaviorAction::RedirectTarget { new_target } => keys.extend(new_target.get_context_keys()),
+                TokenBehaviorAction::Destroy { target }
+                | TokenBehaviorAction::GiveAllTypes { target }
+                | TokenBehaviorAction::AddBehavior { target, .. }
+                | TokenBehaviorAction::RemoveBehavior { target, .. }
+                | TokenBehaviorAction::SetCounter { target, .. }
+                | TokenBehaviorAction::ModifyCounter { target, .. } => keys.extend(target.get_context_keys()),
+                TokenBehaviorAction::DrawToken { .. }
+                | TokenBehaviorAction::DamageHero { .. }
+                | TokenBehaviorAction::Cancel
+                | TokenBehaviorAction::SaveContext { .. }
+                | TokenBehaviorAction::CreateToken { .. } => {}
+            }
+        }
+        keys
+    }
+
+    /// Every token id this behavior creates, summons or replaces a token with.
+    pub fn get_created_tokens(&self) -> Vec<&String> {
+        self.actions.iter().filter_map(|action| match action {
+            TokenBehaviorAction::Replace { replacement, .. } => Some(replacement),
+            TokenBehaviorAction::Summon { token, .. } => Some(token),
+            TokenBehaviorAction::CreateToken { id, .. } => Some(id),
+            _ => None,
+        }).collect()
+    }
+
+    /// Every shared behavior this behavior attaches or removes.
+    pub fn get_shared_behaviors(&self) -> Vec<&String> {
+        self.actions.iter().filter_map(|action| match action {
+            TokenBehaviorAction::AddBehavior { behavior, .. } => Some(behavior),
+            TokenBehaviorAction::RemoveBehavior { behavior, .. } => Some(behavior),
+            _ => None,
+        }).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TokenBehaviorTrigger {
+    /// The zones the token has to be in for this trigger to fire. Tokens in play react by default.
+    #[serde(rename = "in", default = "TriggerZone::in_play", deserialize_with = "deserialize_one_or_many")]
+    pub zones: Vec<TriggerZone>,
     pub when: TokenBehaviorTriggerWhen,
     pub and: Option<TokenBehaviorTriggerAnd>
 }
 
+impl TokenBehaviorTrigger {
+    pub fn is_live_in(&self, location: LocationId) -> bool {
+        let Ok(identity) = location_ids::identify_location(location) else { return false };
+        self.zones.iter().any(|zone| zone.contains(identity))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerZone {
+    Hand,
+    Set,
+    /// Heroes, landscapes, units on the field and the items equipped to them
+    Field,
+    Graveyard,
+}
+
+impl TokenCategory {
+    fn one_attack() -> i32 {
+        1
+    }
+
+    /// Attacks per turn of units, everything else can't attack.
+    pub fn attacks_per_turn(&self) -> i32 {
+        match self {
+            TokenCategory::Unit { attacks_per_turn, .. } => *attacks_per_turn,
+            _ => 0,
+        }
+    }
+}
+
+impl TriggerZone {
+    fn in_play() -> Vec<TriggerZone> {
+        vec!(TriggerZone::Field, TriggerZone::Graveyard)
+    }
+
+    pub fn contains(&self, location: LocationIdentity) -> bool {
+        match self {
+            TriggerZone::Hand => matches!(location, LocationIdentity::Player1Hand | LocationIdentity::Player2Hand),
+            TriggerZone::Set => matches!(location, LocationIdentity::Player1Set | LocationIdentity::Player2Set),
+            TriggerZone::Field => location.is_field() || location.is_item_slot() || matches!(location,
+                LocationIdentity::Player1Hero | LocationIdentity::Player2Hero | LocationIdentity::Player1Landscape | LocationIdentity::Player2Landscape),
+            TriggerZone::Graveyard => matches!(location, LocationIdentity::Player1Graveyard | LocationIdentity::Player2Graveyard),
+        }
+    }
+}
+
+/// Accepts a single value as well as a list, like `in = "hand"` and `in = ["hand", "field"]`.
+fn deserialize_one_or_many<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Vec<T>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec!(value),
+        OneOrMany::Many(values) => values,
+    })
+}
+
 #[derive(Debug, Clone)]
 pub struct TokenBehaviorTriggerWhen {
     pub activator: TokenBehaviorTriggerWhenActivator,
     pub name: TokenBehaviorTriggerWhenName,
 }
 
-#[derive(Debug, Deserialize_enum_str, Clone)]
+#[derive(Debug, Serialize_enum_str, Deserialize_enum_str, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum TokenBehaviorTriggerWhenActivator {
     #[serde(alias = "owner")]
@@ -106,12 +322,14 @@ pub enum TokenBehaviorTriggerWhenActivator {
     Either
 }
 
-#[derive(Debug, Deserialize_enum_str, Clone, PartialEq)]
+#[derive(Debug, Serialize_enum_str, Deserialize_enum_str, Clone, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum TokenBehaviorTriggerWhenName {
     // Generic
-    TurnStarted, // Todo
-    TurnEnded, // Todo
+    #[serde(alias = "turn_start")]
+    TurnStarted,
+    #[serde(alias = "turn_end")]
+    TurnEnded,
 
     // Token
     HasBeenCreated,
@@ -127,7 +345,9 @@ pub enum TokenBehaviorTriggerWhenName {
 
     // Units
     WillBeSummoned,
+    #[serde(alias = "was_summoned")]
     HasBeenSummoned,
+    WillBeSelected,
     WillAttack,
     WillBeAttacked,
     HasAttacked,
@@ -156,6 +376,13 @@ pub enum TokenBehaviorTriggerWhenName {
 
     // Misc (Internal)
     CheckCancel,
+    SelectCastTarget,
+}
+
+impl Serialize for TokenBehaviorTriggerWhen {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{}:{}", self.activator, self.name))
+    }
 }
 
 impl<'de> Deserialize<'de> for TokenBehaviorTriggerWhen {
@@ -187,7 +414,7 @@ impl<'de> Deserialize<'de> for TokenBehaviorTriggerWhen {
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case", tag = "check", content = "with")]
 pub enum TokenBehaviorTriggerAnd {
     TypeContains {
@@ -202,10 +429,21 @@ pub enum TokenBehaviorTriggerAnd {
     AdjacentTo {
         source: UnitTarget,
         target: UnitTarget
+    },
+    SelectionIntention {
+        intention: SelectionIntention,
+    },
+    Counter {
+        target: TokenTarget,
+        counter: String,
+        #[serde(default)]
+        condition: CountCondition,
+        #[serde(alias = "amount")]
+        value: i32,
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum CountCondition {
     Greater,
@@ -216,6 +454,27 @@ pub enum CountCondition {
     NotEqual
 }
 
+/// Why a token is being selected, as stored under `context_keys::SELECTION_INTENTION`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionIntention {
+    Attack,
+}
+
+impl SelectionIntention {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SelectionIntention::Attack => "attack",
+        }
+    }
+}
+
+impl Default for CountCondition {
+    fn default() -> Self {
+        Self::Equal
+    }
+}
+
 impl CountCondition {
     pub fn evaluate(&self, a: i32, b: i32) -> bool {
         match self {
@@ -247,7 +506,7 @@ impl TokenBehaviorTriggerAnd {
                 passed
             }
             TokenBehaviorTriggerAnd::Count { filter, condition, count } => {
-                let mut tokens = resources.token_instances.values().collect::<Vec<&TokenInstance>>();
+                let mut tokens = resources.get_tokens();
                 filter.evaluate(&mut tokens, &context, resources);
                 condition.evaluate(tokens.len() as i32, *count)
             }
@@ -272,11 +531,26 @@ impl TokenBehaviorTriggerAnd {
                 }
                 passed
             }
+            TokenBehaviorTriggerAnd::SelectionIntention { intention } => {
+                context.get(context_keys::SELECTION_INTENTION).map_or(false, |value| value.as_string().map_or(false, |value| value == intention.as_str()))
+            }
+            TokenBehaviorTriggerAnd::Counter { target, counter, condition, value } => {
+                let mut passed = true;
+                for target in target.evaluate(context, resources)? {
+                    let target_instance = resources.token_instances.get(&target).context(format!("Token with the id {target} was not a found in state resources"))?;
+                    let current = target_instance.counters.get(counter).copied().unwrap_or(0);
+                    if condition.evaluate(current, *value) == false {
+                        passed = false;
+                        break;
+                    }
+                }
+                passed
+            }
         })
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum PlayerTarget {
     Owner,
@@ -292,7 +566,7 @@ impl Default for PlayerTarget {
 }
 
 impl PlayerTarget {
-    pub fn evaluate(&self, owner: PlayerId) -> Vec<PlayerId> {
+    pub fn evaluate(&self, owner: PlayerId, resources: &StateResources) -> Vec<PlayerId> {
         match self {
             PlayerTarget::Owner => vec!(owner),
             PlayerTarget::Opponent => {
@@ -302,12 +576,12 @@ impl PlayerTarget {
                 }
             }
             PlayerTarget::Either => vec!(Player1, Player2),
-            PlayerTarget::Random => if fastrand::bool() { vec!(Player1) } else { vec!(Player2) }
+            PlayerTarget::Random => if resources.rng.bool() { vec!(Player1) } else { vec!(Player2) }
         }
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum UnitTarget {
     This,
@@ -318,15 +592,36 @@ pub enum UnitTarget {
     All,
     Context {
         key: String
-    }
+    },
+    /// One token picked at random from what the inner target finds
+    Random {
+        target: Box<UnitTarget>
+    },
 }
 
 impl UnitTarget {
+    pub fn get_types(&self) -> Vec<&String> {
+        match self {
+            UnitTarget::Find { filter } => filter.get_types(),
+            UnitTarget::Random { target } => target.get_types(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn get_context_keys(&self) -> Vec<&String> {
+        match self {
+            UnitTarget::Find { filter } => filter.get_context_keys(),
+            UnitTarget::Context { key } => vec!(key),
+            UnitTarget::Random { target } => target.get_context_keys(),
+            _ => Vec::new(),
+        }
+    }
+
     pub fn evaluate(&self, context: &GameContext, resources: &StateResources) -> Result<Vec<TokenInstanceId>> {
         Ok(match self {
             UnitTarget::This => vec!(context.get(context_keys::ACTION_THIS)?.as_token_instance_id()?),
             UnitTarget::Find { filter } => {
-                let mut tokens = resources.token_instances.values().collect::<Vec<&TokenInstance>>();
+                let mut tokens = resources.get_tokens();
                 filter.evaluate(&mut tokens, context, resources)?;
                 tokens.iter().map(|c| c.instance_id).collect::<Vec<TokenInstanceId>>()
             }
@@ -336,11 +631,12 @@ impl UnitTarget {
                 let equipping_unit = resources.equipment_slot_owners.get(&this_instance.location).context("Item is not in equipment slot")?;
                 vec!(*equipping_unit)
             }
-            UnitTarget::All => todo!(),
-            UnitTarget::Context { key } => {
-                let value = context.get(key)?.as_token_instance_id()?;
-                vec!(context.get(key)?.as_token_instance_id()?)
-            },
+            UnitTarget::All => resources.get_tokens_on_field().iter().map(|c| c.instance_id).collect::<Vec<TokenInstanceId>>(),
+            UnitTarget::Context { key } => vec!(get_context_token(key, context, resources)?),
+            UnitTarget::Random { target } => {
+                let targets = target.evaluate(context, resources)?;
+                if targets.is_empty() { Vec::new() } else { vec!(targets[resources.rng.usize(0..targets.len())]) }
+            }
         })
     }
 }
@@ -351,7 +647,7 @@ impl Default for UnitTarget {
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum TokenTarget {
     This,
@@ -365,11 +661,26 @@ pub enum TokenTarget {
 }
 
 impl TokenTarget {
+    pub fn get_types(&self) -> Vec<&String> {
+        match self {
+            TokenTarget::Find { filter } => filter.get_types(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn get_context_keys(&self) -> Vec<&String> {
+        match self {
+            TokenTarget::Find { filter } => filter.get_context_keys(),
+            TokenTarget::Context { key } => vec!(key),
+            _ => Vec::new(),
+        }
+    }
+
     pub fn evaluate(&self, context: &GameContext, resources: &StateResources) -> Result<Vec<TokenInstanceId>> {
         Ok(match self {
             TokenTarget::This => vec!(context.get(context_keys::ACTION_THIS)?.as_token_instance_id()?),
             TokenTarget::Find { filter } => {
-                let mut tokens = resources.token_instances.values().collect::<Vec<&TokenInstance>>();
+                let mut tokens = resources.get_tokens();
                 filter.evaluate(&mut tokens, context, resources)?;
                 tokens.iter().map(|c| c.instance_id).collect::<Vec<TokenInstanceId>>()
             },
@@ -379,12 +690,23 @@ impl TokenTarget {
                 let equipping_unit = resources.equipment_slot_owners.get(&this_instance.location).context("Item is not in equipment slot")?;
                 vec!(*equipping_unit)
             }
-            TokenTarget::Context { key } => vec!(context.get(key)?.as_token_instance_id()?),
+            TokenTarget::Context { key } => vec!(get_context_token(key, context, resources)?),
         })
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// Looks up a token in the current context first and falls back to what the acting token saved in its personal context.
+fn get_context_token(key: &str, context: &GameContext, resources: &StateResources) -> Result<TokenInstanceId> {
+    if let Ok(value) = context.get(key) {
+        return value.as_token_instance_id();
+    }
+
+    let this = context.get(context_keys::ACTION_THIS)?.as_token_instance_id()?;
+    let this_instance = resources.token_instances.get(&this).context(format!("Token with the id {this} was not a found in state resources"))?;
+    this_instance.personal_context.get(key)?.as_token_instance_id()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum LocationTarget {
     OwnerHand,
@@ -395,18 +717,54 @@ pub enum LocationTarget {
     OpponentGraveyard
 }
 
-#[derive(Deserialize, Debug, Clone)]
+impl LocationTarget {
+    /// The location and the player it belongs to, seen from the given owner.
+    pub fn evaluate(&self, owner: PlayerId, resources: &StateResources) -> (PlayerId, LocationId) {
+        let player_id = match self {
+            LocationTarget::OwnerHand | LocationTarget::OwnerSet | LocationTarget::OwnerGraveyard => owner,
+            LocationTarget::OpponentHand | LocationTarget::OpponentSet | LocationTarget::OpponentGraveyard => owner.opponent(),
+        };
+
+        let location = match self {
+            LocationTarget::OwnerHand | LocationTarget::OpponentHand => resources.get_player(player_id).hand,
+            LocationTarget::OwnerSet | LocationTarget::OpponentSet => resources.get_player(player_id).set,
+            LocationTarget::OwnerGraveyard | LocationTarget::OpponentGraveyard => resources.board.get_side(player_id).graveyard,
+        };
+
+        (player_id, location)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct TokenFilter {
     owned_by: Option<PlayerTarget>,
     adjacent_to: Option<UnitTarget>,
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    is_not: Vec<UnitTarget>,
     contains_types: Option<Vec<String>>,
     id_is: Option<Vec<String>>,
 }
 
 impl TokenFilter {
+    pub fn get_types(&self) -> Vec<&String> {
+        let mut types = self.contains_types.iter().flatten().collect::<Vec<&String>>();
+        if let Some(adjacent_to) = &self.adjacent_to {
+            types.extend(adjacent_to.get_types());
+        }
+        for is_not in &self.is_not {
+            types.extend(is_not.get_types());
+        }
+        types
+    }
+
+    pub fn get_context_keys(&self) -> Vec<&String> {
+        self.adjacent_to.iter().chain(self.is_not.iter()).flat_map(|target| target.get_context_keys()).collect()
+    }
+
     pub fn evaluate(&self, tokens: &mut Vec<&TokenInstance>, context: &GameContext, resources: &StateResources) -> Result<()> {
         if let Some(owned_by) = &self.owned_by {
-            tokens.retain(|c| owned_by.evaluate(context.get(context_keys::OWNER).unwrap().as_player_id().unwrap()).contains(&c.owner))
+            let owners = owned_by.evaluate(context.get(context_keys::OWNER)?.as_player_id()?, resources);
+            tokens.retain(|c| owners.contains(&c.owner))
         }
 
         if let Some(adjacent_to) = &self.adjacent_to {
@@ -430,6 +788,11 @@ impl TokenFilter {
             });
         }
 
+        for is_not in &self.is_not {
+            let excluded = is_not.evaluate(context, resources)?;
+            tokens.retain(|c| excluded.contains(&c.instance_id) == false);
+        }
+
         if let Some(contains_types) = &self.contains_types {
             tokens.retain(|c| {
                 for t in contains_types {
@@ -451,7 +814,7 @@ impl TokenFilter {
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case", tag = "then", content = "with")]
 pub enum TokenBehaviorAction {
     DrawToken {
@@ -510,6 +873,7 @@ pub enum TokenBehaviorAction {
         filter: TokenFilter,
     },
     SaveContext {
+        #[serde(alias = "key")]
         context_key: String,
         personal_key: String,
     },
@@ -519,20 +883,24 @@ pub enum TokenBehaviorAction {
     },
     AddBehavior {
         target: TokenTarget,
+        #[serde(alias = "id")]
         behavior: String,
     },
     RemoveBehavior {
         target: TokenTarget,
+        #[serde(alias = "id")]
         behavior: String,
     },
     SetCounter {
         target: TokenTarget,
         counter: String,
+        #[serde(alias = "amount")]
         value: i32,
     },
     ModifyCounter {
         target: TokenTarget,
         counter: String,
+        #[serde(alias = "value")]
         amount: i32,
     },
     CreateToken {
@@ -547,7 +915,7 @@ impl TokenBehaviorAction {
 
         let result = match self {
             TokenBehaviorAction::DrawToken { target } => {
-                for player in target.evaluate(context.get(context_keys::OWNER)?.as_player_id()?) {
+                for player in target.evaluate(context.get(context_keys::OWNER)?.as_player_id()?, resources) {
                     state.draw_token(player);
                 }
                 TokenBehaviorResult::Ok
@@ -564,7 +932,18 @@ impl TokenBehaviorAction {
 
                 TokenBehaviorResult::Ok
             },
-            TokenBehaviorAction::AddTypes { target, types } => todo!(),
+            TokenBehaviorAction::AddTypes { target, types } => {
+                for target in target.evaluate(context, resources)? {
+                    let target_instance = resources.token_instances.get_mut(&target).unwrap();
+                    for token_type in types {
+                        if target_instance.token_types.contains(token_type) == false {
+                            target_instance.token_types.push(token_type.clone());
+                        }
+                    }
+                    communicator.send_game_instruction(InstructionToClient::UpdateData { token_data: target_instance.clone() }).await?;
+                }
+                TokenBehaviorResult::Ok
+            },
             TokenBehaviorAction::ModifyAttack { target, amount } => {
                 for target in target.evaluate(context, resources)? {
                     let target_instance = resources.token_instances.get_mut(&target).unwrap();
@@ -609,20 +988,104 @@ impl TokenBehaviorAction {
                 }
                 TokenBehaviorResult::Ok
             }
-            TokenBehaviorAction::Summon { target, token } => todo!(),
+            TokenBehaviorAction::Summon { target, token } => {
+                let mut reserved_slots = Vec::new();
+                for target in target.evaluate(context, resources)? {
+                    let owner = resources.token_instances.get(&target).context("Tried to summon next to a token that does not exist")?.owner;
+                    if let Some(slot) = resources.find_free_field_slot(owner, Some(target), &reserved_slots) {
+                        state.summon_new_token(token, owner, slot);
+                        reserved_slots.push(slot);
+                    }
+                }
+                TokenBehaviorResult::Ok
+            },
 
-            TokenBehaviorAction::GiveAllTypes { .. } => todo!(),
+            TokenBehaviorAction::GiveAllTypes { target } => {
+                let all_types = TOKEN_REGISTRY.lock().await.all_types();
+                for target in target.evaluate(context, resources)? {
+                    let target_instance = resources.token_instances.get_mut(&target).unwrap();
+                    for token_type in &all_types {
+                        if target_instance.token_types.contains(token_type) == false {
+                            target_instance.token_types.push(token_type.clone());
+                        }
+                    }
+                    communicator.send_game_instruction(InstructionToClient::UpdateData { token_data: target_instance.clone() }).await?;
+                }
+                TokenBehaviorResult::Ok
+            },
             TokenBehaviorAction::Cancel => TokenBehaviorResult::Cancel,
-            TokenBehaviorAction::SelectUnit { .. } => todo!(),
-            TokenBehaviorAction::SaveContext { .. } => todo!(),
-            TokenBehaviorAction::SumAttack { target, filter } => todo!(),
-            TokenBehaviorAction::AddBehavior { .. } => todo!(),
-            TokenBehaviorAction::RemoveBehavior { .. } => todo!(),
-            TokenBehaviorAction::SetCounter { .. } => todo!(),
-            TokenBehaviorAction::ModifyCounter { .. } => todo!(),
-            TokenBehaviorAction::CreateToken { .. } => todo!(),
+            TokenBehaviorAction::SelectUnit { context_key, filter } => {
+                let mut tokens = resources.get_tokens_on_field();
+                filter.evaluate(&mut tokens, context, resources)?;
+                if tokens.is_empty() == false {
+                    let selected = tokens[resources.rng.usize(0..tokens.len())].instance_id;
+                    context.insert(context_key, ContextValue::TokenInstanceId(selected));
+                }
+                TokenBehaviorResult::Ok
+            },
+            TokenBehaviorAction::SaveContext { context_key, personal_key } => {
+                // Nothing to remember if an earlier action did not find anything
+                if let Ok(value) = context.get(context_key) {
+                    let value = value.clone();
+                    let this_instance = resources.token_instances.get_mut(&this).context("Tried to save context to a token that does not exist")?;
+                    this_instance.personal_context.insert(personal_key, value);
+                }
+                TokenBehaviorResult::Ok
+            },
+            TokenBehaviorAction::SumAttack { target, filter } => {
+                let mut tokens = resources.get_tokens_on_field();
+                filter.evaluate(&mut tokens, context, resources)?;
+                let attack = tokens.iter().map(|c| c.current_stats.attack).sum::<i32>();
+                for target in target.evaluate(context, resources)? {
+                    let target_instance = resources.token_instances.get_mut(&target).unwrap();
+                    target_instance.current_stats.attack = attack;
+                    communicator.send_game_instruction(InstructionToClient::UpdateData { token_data: target_instance.clone() }).await?;
+                }
+                TokenBehaviorResult::Ok
+            },
+            TokenBehaviorAction::AddBehavior { target, behavior } => {
+                let new_behavior = TOKEN_REGISTRY.lock().await.behavior_registry.get_data(behavior)?.clone();
+                for target in target.evaluate(context, resources)? {
+                    let target_instance = resources.token_instances.get_mut(&target).unwrap();
+                    if target_instance.behaviors.iter().any(|b| b.id.as_ref() == Some(behavior)) {
+                        continue;
+                    }
+                    target_instance.behaviors.push(new_behavior.clone());
+                    communicator.send_game_instruction(InstructionToClient::UpdateBehaviors { token_data: target_instance.clone() }).await?;
+                }
+                TokenBehaviorResult::Ok
+            },
+            TokenBehaviorAction::RemoveBehavior { target, behavior } => {
+                for target in target.evaluate(context, resources)? {
+                    let target_instance = resources.token_instances.get_mut(&target).unwrap();
+                    target_instance.behaviors.retain(|b| b.id.as_ref() != Some(behavior));
+                    communicator.send_game_instruction(InstructionToClient::UpdateBehaviors { token_data: target_instance.clone() }).await?;
+                }
+                TokenBehaviorResult::Ok
+            },
+            TokenBehaviorAction::SetCounter { target, counter, value } => {
+                for target in target.evaluate(context, resources)? {
+                    let target_instance = resources.token_instances.get_mut(&target).unwrap();
+                    target_instance.counters.insert(counter.clone(), *value);
+                    communicator.send_game_instruction(InstructionToClient::UpdateCounters { token_data: target_instance.clone() }).await?;
+                }
+                TokenBehaviorResult::Ok
+            },
+            TokenBehaviorAction::ModifyCounter { target, counter, amount } => {
+                for target in target.evaluate(context, resources)? {
+                    let target_instance = resources.token_instances.get_mut(&target).unwrap();
+                    *target_instance.counters.entry(counter.clone()).or_insert(0) += amount;
+                    communicator.send_game_instruction(InstructionToClient::UpdateCounters { token_data: target_instance.clone() }).await?;
+                }
+                TokenBehaviorResult::Ok
+            },
+            TokenBehaviorAction::CreateToken { location, id } => {
+                let (player_id, location) = location.evaluate(context.get(context_keys::OWNER)?.as_player_id()?, resources);
+                state.create_token(id, player_id, location);
+                TokenBehaviorResult::Ok
+            },
             TokenBehaviorAction::DamageHero { target, amount } => {
-                for target in target.evaluate(context.get(context_keys::OWNER)?.as_player_id()?) {
+                for target in target.evaluate(context.get(context_keys::OWNER)?.as_player_id()?, resources) {
                     state.deal_effect_damage(this, resources.get_player(target).hero, *amount as i32);
                 }
                 TokenBehaviorResult::Ok
@@ -634,10 +1097,26 @@ impl TokenBehaviorAction {
                 TokenBehaviorResult::Ok
             },
             TokenBehaviorAction::RedirectTarget { new_target } => {
-                let new_target = new_target.evaluate(context, resources)?;
-                let new_target = new_target.first();
+                // The attacker can't be made to attack itself, and only units on the field can take the hit.
+                // The new defender is selected for the attack again, see StateTransitionGroup::process
+                let attacker = context.get(context_keys::ATTACKER)?.as_token_instance_id()?;
+                let candidates = |target: &UnitTarget| -> Result<Vec<TokenInstanceId>> {
+                    Ok(target.evaluate(context, resources)?.into_iter().filter(|target| {
+                        *target != attacker && resources.token_instances.get(target).map_or(false, |target| {
+                            location_ids::identify_location(target.location).map_or(false, |location| location.is_field())
+                        })
+                    }).collect())
+                };
+                let new_target = match new_target {
+                    // Pick among the tokens that can take the hit, not among everything the inner target finds
+                    UnitTarget::Random { target } => {
+                        let candidates = candidates(target)?;
+                        if candidates.is_empty() { None } else { Some(candidates[resources.rng.usize(0..candidates.len())]) }
+                    }
+                    new_target => candidates(new_target)?.first().copied(),
+                };
                 if let Some(new_target) = new_target {
-                    todo!();
+                    context.insert(context_keys::DEFENDER, ContextValue::TokenInstanceId(new_target));
                 }
                 TokenBehaviorResult::Ok
             }
@@ -4,7 +4,7 @@ use std::ops::Not;
 
 use color_eyre::eyre::{ContextCompat, eyre};
 use color_eyre::Result;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use toml::Table;
 use walkdir::WalkDir;
 use crate::game::board::Board;
@@ -13,6 +13,7 @@ use crate::game::tokens::token_deserializer::{TokenData, TokenBehavior};
 use crate::game::game_communicator::GameCommunicator;
 use crate::game::id_types::{TokenInstanceId, LocationId, PlayerId, ServerInstanceId};
 use crate::game::state_resources::StateResources;
+use crate::game::game_context::GameContext;
 
 #[derive(Clone, Debug)]
 pub struct TokenInstance {
@@ -27,9 +28,12 @@ pub struct TokenInstance {
     pub equipment_slots: Vec<LocationId>,
     pub token_types: Vec<String>,
     pub hidden: bool,
+    pub counters: HashMap<String, i32>,
+    /// Values a token remembers between triggers, e.g. a unit it has been bound to.
+    pub personal_context: GameContext,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct UnitStats {
     pub health: i32,
     pub defense: i32,
@@ -50,6 +54,11 @@ impl UnitStats {
 }
 
 impl TokenInstance {
+    /// Owners always see their own tokens, everyone else only once the token has been revealed.
+    pub fn is_visible_to(&self, player_id: PlayerId) -> bool {
+        self.owner == player_id || self.hidden == false
+    }
+
     pub fn is_alive(&self, resources: &StateResources, board: &Board) -> bool {
         let graveyard_1 = resources.locations.get(&board.side_1.graveyard).unwrap();
         let graveyard_2 = resources.locations.get(&board.side_2.graveyard).unwrap();
@@ -0,0 +1,180 @@
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use crate::game::game_context::context_keys;
+use crate::game::new_state_machine;
+use crate::game::tokens::token_deserializer::{TokenBehavior, TokenBehaviorAction, TokenCategory};
+use crate::game::tokens::token_registry::TokenRegistry;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LintSeverity {
+    Error,
+    Warning,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LintKind {
+    LoadFailed,
+    UnknownToken,
+    UnknownContextKey,
+    UnimplementedTrigger,
+    EmptyLandscape,
+    ZeroHealthHero,
+    UnknownType,
+}
+
+/// A problem with the token data the server would otherwise only run into during a game.
+#[derive(Serialize, Debug, Clone)]
+pub struct LintIssue {
+    pub severity: LintSeverity,
+    pub kind: LintKind,
+    pub token: Option<String>,
+    pub behavior: Option<String>,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LintReport {
+    pub errors: usize,
+    pub warnings: usize,
+    pub issues: Vec<LintIssue>,
+}
+
+impl LintReport {
+    pub fn new(issues: Vec<LintIssue>) -> Self {
+        LintReport {
+            errors: issues.iter().filter(|issue| issue.severity == LintSeverity::Error).count(),
+            warnings: issues.iter().filter(|issue| issue.severity == LintSeverity::Warning).count(),
+            issues,
+        }
+    }
+
+    /// Loads the data directory and lints it. Data that doesn't load at all is reported as a single error.
+    pub fn from_data_directory(path: &str) -> Self {
+        match TokenRegistry::from_data_directory(path) {
+            Ok(registry) => Self::new(lint(&registry)),
+            Err(e) => Self::new(vec!(LintIssue {
+                severity: LintSeverity::Error,
+                kind: LintKind::LoadFailed,
+                token: None,
+                behavior: None,
+                message: e.to_string(),
+            })),
+        }
+    }
+}
+
+/// Checks every token and shared behavior in the registry.
+pub fn lint(registry: &TokenRegistry) -> Vec<LintIssue> {
+    let mut issues = Vec::new();
+
+    // Tokens can remember values between triggers in their personal context, those keys are always available
+    let personal_keys = registry.token_registry.values()
+        .flat_map(|token| token.behaviors.iter())
+        .chain(registry.behavior_registry.behavior_registry.values())
+        .flat_map(|behavior| behavior.actions.iter())
+        .filter_map(|action| match action {
+            TokenBehaviorAction::SaveContext { personal_key, .. } => Some(personal_key.as_str()),
+            _ => None,
+        })
+        .collect::<HashSet<&str>>();
+
+    let mut behavior_ids = registry.behavior_registry.behavior_registry.keys().collect::<Vec<&String>>();
+    behavior_ids.sort();
+    for id in behavior_ids {
+        let behavior = &registry.behavior_registry.behavior_registry[id];
+        lint_behavior(registry, behavior, None, id.clone(), &personal_keys, &mut issues);
+    }
+
+    let mut tokens = registry.token_registry.values().collect::<Vec<_>>();
+    tokens.sort_by(|a, b| a.id.cmp(&b.id));
+    for token in tokens {
+        match token.token_category {
+            TokenCategory::Hero { health, .. } if health <= 0 => issues.push(LintIssue {
+                severity: LintSeverity::Error,
+                kind: LintKind::ZeroHealthHero,
+                token: Some(token.id.clone()),
+                behavior: None,
+                message: format!("Hero has {} health", health),
+            }),
+            TokenCategory::Landscape { ref slots } if slots.is_empty() => issues.push(LintIssue {
+                severity: LintSeverity::Error,
+                kind: LintKind::EmptyLandscape,
+                token: Some(token.id.clone()),
+                behavior: None,
+                message: "Landscape has no slots".to_string(),
+            }),
+            _ => {}
+        }
+
+        let mut available_keys = personal_keys.clone();
+        if let TokenCategory::Command { cast_target: Some(_) } = token.token_category {
+            available_keys.insert(context_keys::CAST_TARGET);
+        }
+
+        for (index, behavior) in token.behaviors.iter().enumerate() {
+            let name = behavior.id.clone().or(behavior.name.clone()).unwrap_or(format!("#{}", index));
+            lint_behavior(registry, behavior, Some(&token.id), name, &available_keys, &mut issues);
+        }
+    }
+
+    for unknown in registry.unknown_types() {
+        issues.push(LintIssue {
+            severity: LintSeverity::Warning,
+            kind: LintKind::UnknownType,
+            message: unknown.to_string(),
+            token: unknown.token,
+            behavior: unknown.behavior,
+        });
+    }
+
+    issues
+}
+
+fn lint_behavior(registry: &TokenRegistry, behavior: &TokenBehavior, token: Option<&String>, name: String, available_keys: &HashSet<&str>, issues: &mut Vec<LintIssue>) {
+    let mut issue = |severity, kind, message| issues.push(LintIssue {
+        severity,
+        kind,
+        token: token.cloned(),
+        behavior: Some(name.clone()),
+        message,
+    });
+
+    for id in behavior.get_created_tokens() {
+        if registry.token_registry.contains_key(id) == false {
+            issue(LintSeverity::Error, LintKind::UnknownToken, format!("Token not found: {}", id));
+        }
+    }
+
+    // A key has to be there no matter which of the triggers started the behavior
+    let mut supplied: Option<HashSet<&str>> = None;
+    for trigger in &behavior.triggers {
+        let Some(keys) = new_state_machine::supplied_context_keys(trigger.when.name.clone()) else {
+            issue(LintSeverity::Warning, LintKind::UnimplementedTrigger, format!("Trigger {:?} is never fired", trigger.when.name));
+            continue;
+        };
+        let keys = keys.into_iter().collect::<HashSet<&str>>();
+        supplied = Some(match supplied {
+            Some(supplied) => supplied.intersection(&keys).copied().collect(),
+            None => keys,
+        });
+    }
+
+    let Some(supplied) = supplied else { return };
+    let selected = behavior.actions.iter().filter_map(|action| match action {
+        TokenBehaviorAction::SelectUnit { context_key, .. } => Some(context_key.as_str()),
+        _ => None,
+    }).collect::<HashSet<&str>>();
+
+    let mut reported = HashSet::new();
+    for key in behavior.get_context_keys() {
+        let key = key.as_str();
+        if supplied.contains(key) || selected.contains(key) || available_keys.contains(key) || reported.insert(key) == false {
+            continue;
+        }
+        issue(LintSeverity::Error, LintKind::UnknownContextKey, format!("No trigger supplies the context key \"{}\"", key));
+    }
+}
@@ -1,23 +1,174 @@
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::fs;
 use color_eyre::eyre::{ContextCompat, eyre};
 
 use color_eyre::Result;
 use walkdir::WalkDir;
 
-use crate::game::tokens::token_deserializer::{TokenData, TokenCategory};
+use crate::game::tokens::behavior_registry::BehaviorRegistry;
+use crate::game::tokens::counter_registry::CounterRegistry;
+use crate::game::tokens::keyword_registry::KeywordRegistry;
+use crate::game::tokens::text_template::TextTemplates;
+use crate::game::tokens::type_registry::TypeRegistry;
+use crate::game::tokens::token_deserializer::{TokenData, TokenCategory, TokenBehavior};
 use crate::game::tokens::token_instance::{TokenInstance, UnitStats};
 use crate::game::id_types::{TokenInstanceId, LocationId, PlayerId};
+use crate::game::game_context::GameContext;
+
+/// A type that types.toml doesn't declare, with the token or shared behavior that uses it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UnknownType {
+    pub token: Option<String>,
+    pub behavior: Option<String>,
+    pub token_type: String,
+}
+
+impl Display for UnknownType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (&self.token, &self.behavior) {
+            (Some(token), _) => write!(f, "Token {} uses an unknown type: {}", token, self.token_type),
+            (None, behavior) => write!(f, "Behavior {} uses an unknown type: {}", behavior.as_deref().unwrap_or(""), self.token_type),
+        }
+    }
+}
 
 pub struct TokenRegistry {
     pub token_registry: HashMap<String, &'static TokenData>,
+    pub counter_registry: CounterRegistry,
+    pub behavior_registry: BehaviorRegistry,
+    pub keyword_registry: KeywordRegistry,
+    pub type_registry: TypeRegistry,
+    /// Problems that don't stop the data from loading, like types missing from types.toml or texts naming tokens that don't exist.
+    pub warnings: Vec<String>,
 }
 
 impl TokenRegistry {
-    pub fn from_directory(path: &str) -> Result<Self> {
-        println!("Loading tokens from {}", path);
+    /// Loads everything under the data directory: counters, shared behaviors, keywords and types first, then the tokens that refer to them.
+    pub fn from_data_directory(path: &str) -> Result<Self> {
+        let counter_registry = CounterRegistry::from_file(&format!("{}/counters.toml", path))?;
+        let mut behavior_registry = BehaviorRegistry::from_file(&format!("{}/behaviors.toml", path))?;
+        let keyword_registry = KeywordRegistry::from_file(&format!("{}/keywords.toml", path))?;
+        let type_registry = TypeRegistry::from_file(&format!("{}/types.toml", path))?;
+        let mut tokens = Self::load_tokens(&format!("{}/tokens", path))?;
 
-        let mut registry: HashMap<String, &'static TokenData> = HashMap::new();
+        let templates = TextTemplates {
+            keywords: &keyword_registry,
+            types: &type_registry,
+            token_names: tokens.iter().map(|token| (token.id.clone(), token.name.clone())).collect(),
+        };
+        let mut missing_tokens = Vec::new();
+        for (id, behavior) in behavior_registry.behavior_registry.iter() {
+            for missing in templates.missing_behavior_tokens(behavior) {
+                missing_tokens.push(format!("Behavior {} refers to an unknown token: {}", id, missing));
+            }
+        }
+        for token in tokens.iter() {
+            let description = token.description.iter().flat_map(|description| templates.missing_tokens(description));
+            for missing in description.chain(token.behaviors.iter().flat_map(|behavior| templates.missing_behavior_tokens(behavior))) {
+                missing_tokens.push(format!("Token {} refers to an unknown token: {}", token.id, missing));
+            }
+        }
+
+        for (id, behavior) in behavior_registry.behavior_registry.iter_mut() {
+            templates.expand_behavior(behavior).map_err(|e| eyre!("Behavior {}: {}", id, e))?;
+        }
+        for token in tokens.iter_mut() {
+            Self::expand_token_texts(token, &templates).map_err(|e| eyre!("Token {}: {}", token.id, e))?;
+        }
+
+        let token_registry = tokens.into_iter()
+            .map(|token| (token.id.clone(), &*Box::leak(Box::new(token))))
+            .collect();
+
+        let mut registry = TokenRegistry {
+            token_registry,
+            counter_registry,
+            behavior_registry,
+            keyword_registry,
+            type_registry,
+            warnings: Vec::new(),
+        };
+        registry.validate()?;
+        registry.warnings = registry.unknown_types().iter().map(|unknown| unknown.to_string()).chain(missing_tokens).collect();
+        registry.warnings.sort();
+        registry.warnings.dedup();
+        for warning in &registry.warnings {
+            eprintln!("Warning: {}", warning);
+        }
+        Ok(registry)
+    }
+
+    /// Makes sure every counter and shared behavior referenced by a token or a shared behavior exists.
+    fn validate(&self) -> Result<()> {
+        for (id, behavior) in &self.behavior_registry.behavior_registry {
+            self.validate_behavior(behavior).map_err(|e| eyre!("Behavior {}: {}", id, e))?;
+        }
+
+        for token in self.token_registry.values() {
+            for id in &token.shared_behaviors {
+                self.behavior_registry.get_data(id).map_err(|e| eyre!("Token {}: {}", token.id, e))?;
+            }
+            for behavior in &token.behaviors {
+                self.validate_behavior(behavior).map_err(|e| eyre!("Token {}: {}", token.id, e))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists every type used by a token or a behavior that is not declared in types.toml.
+    /// Types used by tokens and shared behaviors that types.toml doesn't declare, sorted by where they are used.
+    pub fn unknown_types(&self) -> Vec<UnknownType> {
+        let mut unknown = Vec::new();
+        let is_unknown = |token_type: &String| self.type_registry.type_registry.contains_key(token_type) == false;
+        for (id, behavior) in &self.behavior_registry.behavior_registry {
+            for token_type in behavior.get_types().into_iter().filter(|token_type| is_unknown(token_type)) {
+                unknown.push(UnknownType { token: None, behavior: Some(id.clone()), token_type: token_type.clone() });
+            }
+        }
+
+        for token in self.token_registry.values() {
+            for token_type in token.types.iter().filter(|token_type| is_unknown(token_type)) {
+                unknown.push(UnknownType { token: Some(token.id.clone()), behavior: None, token_type: token_type.clone() });
+            }
+            for (index, behavior) in token.behaviors.iter().enumerate() {
+                let name = behavior.id.clone().or(behavior.name.clone()).unwrap_or(format!("#{}", index));
+                for token_type in behavior.get_types().into_iter().filter(|token_type| is_unknown(token_type)) {
+                    unknown.push(UnknownType { token: Some(token.id.clone()), behavior: Some(name.clone()), token_type: token_type.clone() });
+                }
+            }
+        }
+
+        unknown.sort();
+        unknown.dedup();
+        unknown
+    }
+
+    fn validate_behavior(&self, behavior: &TokenBehavior) -> Result<()> {
+        for counter in behavior.get_counters() {
+            self.counter_registry.get_data(counter)?;
+        }
+        for id in behavior.get_shared_behaviors() {
+            self.behavior_registry.get_data(id)?;
+        }
+        Ok(())
+    }
+
+    fn expand_token_texts(token: &mut TokenData, templates: &TextTemplates) -> Result<()> {
+        if let Some(description) = &token.description {
+            token.description = Some(templates.expand(description)?);
+        }
+        for behavior in token.behaviors.iter_mut() {
+            templates.expand_behavior(behavior)?;
+        }
+        Ok(())
+    }
+
+    fn load_tokens(path: &str) -> Result<Vec<TokenData>> {
+        eprintln!("Loading tokens from {}", path);
+
+        let mut tokens = Vec::new();
 
         for dir in WalkDir::new(path).into_iter().filter_map(|entry| entry.ok()) {
             if dir.path().is_file() == false {
@@ -25,23 +176,23 @@ impl TokenRegistry {
             }
 
             let id = dir.path().with_extension("").file_name().and_then(|name| name.to_str()).unwrap().to_string();
-            println!("Loading token: {}", id);
-            let mut token: Box<TokenData> = Box::new(toml::from_str(&fs::read_to_string(dir.path())?)?);
-            token.id = id.clone();
-            registry.insert(
-                id,
-                Box::leak(token)
-            );
+            eprintln!("Loading token: {}", id);
+            let mut token: TokenData = toml::from_str(&fs::read_to_string(dir.path())?)?;
+            token.id = id;
+            tokens.push(token);
         }
 
-        Ok(TokenRegistry {
-            token_registry: registry
-        })
+        Ok(tokens)
     }
 
     pub fn instance_token(&self, id: &str, instance_id: TokenInstanceId, location: LocationId, owner: PlayerId) -> Result<TokenInstance> {
         let token = self.token_registry.get(id).context(eyre!("Token not found: {}", id))?;
 
+        let mut behaviors = token.behaviors.clone();
+        for shared_behavior in &token.shared_behaviors {
+            behaviors.push(self.behavior_registry.get_data(shared_behavior)?.clone());
+        }
+
         let mut health = 0;
         let mut defense = 0;
         let mut attack = 0;
@@ -52,13 +203,13 @@ impl TokenRegistry {
                 defense = d;
             }
             TokenCategory::Landscape { .. } => {}
-            TokenCategory::Unit { health: h, attack: a, defense: d } => {
+            TokenCategory::Unit { health: h, attack: a, defense: d, .. } => {
                 health = h;
                 attack = a;
                 defense = d;
             }
             TokenCategory::Item => {}
-            TokenCategory::Command => {}
+            TokenCategory::Command { .. } => {}
         }
 
         Ok(TokenInstance {
@@ -66,17 +217,30 @@ impl TokenRegistry {
             owner,
             location,
             instance_id,
-            behaviors: token.behaviors.clone(),
+            behaviors,
             cost: token.cost,
             base_stats: UnitStats { health, defense, attack },
             current_stats: UnitStats { health, defense, attack },
             token_types: token.types.clone(),
             equipment_slots: Vec::new(),
-            hidden: true
+            hidden: true,
+            counters: HashMap::new(),
+            personal_context: GameContext::new(),
         })
     }
 
     pub fn get_data(&self, id: &str) -> Result<&TokenData> {
         Ok(*self.token_registry.get(id).context(eyre!("Token not found: {}", id))?)
     }
+
+    /// Every type declared in types.toml or used by at least one registered token.
+    pub fn all_types(&self) -> Vec<String> {
+        let mut types = self.token_registry.values()
+            .flat_map(|token| token.types.iter().cloned())
+            .chain(self.type_registry.type_registry.keys().cloned())
+            .collect::<Vec<String>>();
+        types.sort();
+        types.dedup();
+        types
+    }
 }
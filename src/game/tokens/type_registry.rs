@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use std::fs;
+use color_eyre::eyre::{ContextCompat, eyre};
+
+use color_eyre::Result;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TypeData {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+struct TypeFile {
+    #[serde(rename = "type", default)]
+    types: Vec<TypeData>,
+}
+
+pub struct TypeRegistry {
+    pub type_registry: HashMap<String, TypeData>,
+}
+
+impl TypeRegistry {
+    pub fn from_file(path: &str) -> Result<Self> {
+        eprintln!("Loading types from {}", path);
+
+        let file: TypeFile = toml::from_str(&fs::read_to_string(path)?)?;
+        let mut registry = HashMap::new();
+        for token_type in file.types {
+            if let Some(existing) = registry.insert(token_type.id.clone(), token_type) {
+                return Err(eyre!("Type {} is defined more than once", existing.id));
+            }
+        }
+
+        Ok(TypeRegistry {
+            type_registry: registry
+        })
+    }
+
+    pub fn get_data(&self, id: &str) -> Result<&TypeData> {
+        self.type_registry.get(id).context(eyre!("Type not found: {}", id))
+    }
+
+    /// Display names of all known types as (id, name), sorted by id.
+    pub fn get_type_names(&self) -> Vec<(String, String)> {
+        let mut names = self.type_registry.values()
+            .map(|token_type| (token_type.id.clone(), token_type.name.clone()))
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
@@ -8,24 +8,45 @@ use once_cell::sync::Lazy;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex;
 use tokio_tungstenite::tungstenite::handshake::server::Request;
-use tokio_tungstenite::tungstenite::handshake::server::Response;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Response};
+use tokio_tungstenite::tungstenite::http::{HeaderValue, StatusCode};
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
 
 use game::game_service;
+use crate::game::game_lobby::{GameLobby, LobbyJoin};
+use crate::game::game_lobby;
 use crate::game::tokens::token_deserializer::{TokenData, TokenBehaviorTriggerWhenActivator};
 use crate::game::tokens::token_registry::TokenRegistry;
+use crate::game::tokens::token_linter::LintReport;
+use crate::game::game_communicator::Connection;
+use crate::game::protocol::WireFormat;
+use crate::game::replay::{Replay, ReplayResult};
 
 mod game;
 mod token_finder;
 
 pub static TOKEN_REGISTRY: Lazy<Mutex<TokenRegistry>> = Lazy::new(|| {
-    Mutex::new(TokenRegistry::from_directory("data/tokens").unwrap())
+    Mutex::new(TokenRegistry::from_data_directory("data").unwrap())
+});
+
+pub static GAME_LOBBY: Lazy<Mutex<GameLobby>> = Lazy::new(|| {
+    Mutex::new(GameLobby::new())
 });
 
 #[tokio::main]
 async fn main() -> Result<()> {
     color_eyre::install()?;
 
-    game::tokens::token_registry::TokenRegistry::from_directory("data/tokens")?;
+    let args = std::env::args().collect::<Vec<String>>();
+    if args.get(1).map(|arg| arg.as_str()) == Some("lint") {
+        return lint(&args[2..]);
+    }
+    if args.get(1).map(|arg| arg.as_str()) == Some("replay") {
+        return replay(&args[2..]).await;
+    }
+
+    game::tokens::token_registry::TokenRegistry::from_data_directory("data")?;
 
     println!("Starting TcpListener");
 
@@ -38,14 +59,70 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// `lint [data directory] [--output <file>]` checks the token data and writes the report as JSON.
+/// Exits with 1 if there are any errors, so it can gate merges.
+fn lint(args: &[String]) -> Result<()> {
+    let mut data_directory = "data";
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output = Some(args.next().ok_or(eyre!("--output needs a file"))?),
+            _ => data_directory = arg,
+        }
+    }
+
+    let report = LintReport::from_data_directory(data_directory);
+    let json = serde_json::to_string_pretty(&report)?;
+    match output {
+        Some(path) => fs::write(path, json)?,
+        None => println!("{}", json),
+    }
+
+    if report.errors > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// `replay <file>` plays a recorded game again and checks that the server still sends the same messages.
+/// Exits with 1 if the game played out differently.
+async fn replay(args: &[String]) -> Result<()> {
+    let path = args.first().ok_or(eyre!("replay needs a replay file"))?;
+    match Replay::from_file(path)?.verify().await? {
+        ReplayResult::Matches { messages } => println!("Replay matches, {} messages sent", messages),
+        ReplayResult::Diverged { index, expected, actual } => {
+            println!("Replay diverged at message {}", index);
+            println!("Expected: {}", expected.unwrap_or("nothing".to_string()));
+            println!("Actual:   {}", actual.unwrap_or("nothing".to_string()));
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
 async fn accept_connection(stream: TcpStream) {
     let mut service_type = ServiceType::None;
+    let mut session = String::new();
+    let mut rejoin_token = None;
+    let mut format = WireFormat::Legacy;
 
-    let callback = |req: &Request, response: Response| {
+    let callback = |req: &Request, mut response: Response| {
         // switch on the path
         match req.uri().path() {
             "/game" => {
                 service_type = ServiceType::Game;
+                let Some(requested) = game_lobby::get_session(req.uri().query()) else {
+                    let mut error = ErrorResponse::new(Some("Join a game with /game?session=<name>".to_string()));
+                    *error.status_mut() = StatusCode::BAD_REQUEST;
+                    return Err(error);
+                };
+                session = requested;
+                rejoin_token = game_lobby::get_rejoin_token(req.uri().query());
+                format = WireFormat::negotiate(req.headers().get("Sec-WebSocket-Protocol").and_then(|value| value.to_str().ok()));
+                if let Some(subprotocol) = format.subprotocol() {
+                    response.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static(subprotocol));
+                }
                 Ok(response)
             }
             "/tokenfinder" => {
@@ -59,15 +136,32 @@ async fn accept_connection(stream: TcpStream) {
         }
     };
 
-    let websocket = tokio_tungstenite::accept_hdr_async(stream, callback).await.unwrap();
+    let websocket = match tokio_tungstenite::accept_hdr_async(stream, callback).await {
+        Ok(websocket) => websocket,
+        Err(e) => {
+            eprintln!("Handshake failed: {}", e);
+            return;
+        }
+    };
 
     match service_type {
         ServiceType::None => {
             println!("No service type found");
         }
         ServiceType::Game => {
-            if let Err(e) = game_service::game_service(websocket).await {
-                eprintln!("{:?}", e);
+            let join = GAME_LOBBY.lock().await.join(&session, rejoin_token.as_deref(), Connection::new(websocket, format));
+            match join {
+                LobbyJoin::Waiting => println!("Waiting for second player in session \"{}\"", session),
+                LobbyJoin::Rejoined => println!("Player rejoined session \"{}\"", session),
+                LobbyJoin::Refused(mut connection) => {
+                    println!("Refused a new player in running session \"{}\"", session);
+                    let _ = connection.websocket.close(Some(CloseFrame { code: CloseCode::Policy, reason: "This session is already being played".into() })).await;
+                }
+                LobbyJoin::Matched { player_1, player_2, rejoin_tokens } => {
+                    if let Err(e) = game_service::game_service(session, player_1, player_2, rejoin_tokens).await {
+                        eprintln!("{:?}", e);
+                    }
+                }
             }
         },
         ServiceType::TokenFinder => {
@@ -10,7 +10,7 @@ pub async fn finder_service(websocket: WebSocketStream<TcpStream>) -> Result<()>
     println!("Starting Token Finder Service");
     let mut communicator = GameCommunicator::new(websocket);
     loop {
-        let msg = communicator.read_message().await?;
+        let (_, msg) = communicator.read_message().await?;
 
         let message = msg.into_text().unwrap();
 